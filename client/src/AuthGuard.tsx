@@ -7,16 +7,30 @@ import { cont, butt } from "./AuthGuard.css.ts";
 const authw = {
   status: async () => (await auth.check()).refresh,
   signup: async () => {
-    switch (await auth.register()) {
+    const name = prompt("user name");
+    if (!name) {
+      return;
+    }
+
+    const invitation = prompt("invitation code (if any)") || undefined;
+
+    switch (await auth.register(name, invitation)) {
       case "success":
-        break;
+        return name;
+
+      case "forbidden":
+        throw new Error("invitation is required");
 
       case "unauthorized":
         throw new Error("unhandlable error");
     }
   },
-  login: async () => {
-    switch (await auth.claim()) {
+  login: async (name = prompt("user name")) => {
+    if (!name) {
+      return;
+    }
+
+    switch (await auth.claim(name)) {
       case "success":
         break;
 
@@ -37,8 +51,12 @@ export const AuthGuard = () => {
       return;
     }
 
-    await authw.signup();
-    await authw.login();
+    const name = await authw.signup();
+    if (!name) {
+      return;
+    }
+
+    await authw.login(name);
 
     setPassed(await authw.status());
   }, [setPassed]);
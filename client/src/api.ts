@@ -4,6 +4,7 @@ import * as auth from "./auth";
 export interface Post extends z.TypeOf<typeof Post> {}
 export const Post = z.object({
   id: z.number(),
+  authorId: z.number(),
   content: z.object({
     src: z.string(),
     html: z.string(),
@@ -13,39 +14,72 @@ export const Post = z.object({
   isDeleted: z.boolean(),
 });
 
+export interface Page extends z.TypeOf<typeof Page> {}
+export const Page = z.object({
+  items: Post.array(),
+  next: z.string().nullable(),
+});
+
 type Schema = {
   "/posts": {
     GET: {
       req: null;
-      res: Post[];
+      res: Page;
     };
     POST: {
       req: { content: string };
       res: Post;
     };
   };
+  [_: `/posts?cursor=${string}`]: {
+    GET: {
+      req: null;
+      res: Page;
+    };
+  };
   [_: `/posts/${number}`]: {
     GET: {
       req: null;
       res: Post | null;
     };
     PATCH: {
-      req: { content: string } | { isDeleted: boolean };
+      req: { content: string } | { isDeleted: boolean } | { revertTo: string };
       res: Post;
     };
   };
+  [_: `/tags/${string}/posts`]: {
+    GET: {
+      req: null;
+      res: Page;
+    };
+  };
+  [_: `/tags/${string}/posts?cursor=${string}`]: {
+    GET: {
+      req: null;
+      res: Page;
+    };
+  };
 };
 
 type Validator = { req: z.ZodSchema; res: z.ZodSchema };
 
 const getValidator = (url: string, method: string): Validator => {
   const routes = [
+    [
+      /\/tags\/[^/]+\/posts/,
+      {
+        GET: {
+          req: z.null(),
+          res: Page,
+        } /* satisfies Validator */,
+      },
+    ],
     [
       /\/posts/,
       {
         GET: {
           req: z.null(),
-          res: Post.array(),
+          res: Page,
         } /* satisfies Validator */,
         POST: {
           req: z.object({ content: z.string() }),
@@ -64,6 +98,7 @@ const getValidator = (url: string, method: string): Validator => {
           req: z.union([
             z.object({ content: z.string() }),
             z.object({ isDeleted: z.boolean() }),
+            z.object({ revertTo: z.string() }),
           ]),
           res: Post,
         } /* satisfies Validator */,
@@ -1,6 +1,6 @@
 import { style } from "@vanilla-extract/css";
 
-import { colors } from "./styles.css.ts";
+import { colors, c } from "./styles.css.ts";
 
 export const root = style({
   position: "absolute",
@@ -50,6 +50,25 @@ export const space = style({
   },
 });
 
+export const more = style({
+  display: "block",
+
+  padding: ".5rem 1rem",
+  margin: "0 auto",
+
+  background: colors.slate[300],
+
+  borderRadius: c.inf,
+  border: `2px solid ${colors.slate[400]}`,
+
+  transition: "background 150ms ease-in-out",
+
+  ":hover": {
+    background: colors.slate[400],
+    borderColor: colors.slate[500],
+  },
+});
+
 export const f__k = style({
   position: "relative",
 
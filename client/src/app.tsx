@@ -1,21 +1,44 @@
 import { useEffect } from "preact/hooks";
 
 import { fetchAPI } from "./api";
-import { posts } from "./posts";
+import { posts, next } from "./posts";
 
 import { AuthGuard } from "./AuthGuard";
 import { ShowPost } from "./ShowPost";
 import { Submit } from "./Submit";
 
-import { root, cont, list, elem, space, f__k } from "./app.css.ts";
+import { root, cont, list, elem, space, more, f__k } from "./app.css.ts";
 import { apply } from "./styles.css.ts";
 
+// hashtags link to `?tag=<tag>`, showing only the posts with it
+const tag = new URLSearchParams(location.search).get("tag");
+
+const timeline = (cursor?: string) => {
+  const path =
+    tag === null
+      ? ("/posts" as const)
+      : (`/tags/${encodeURIComponent(tag)}/posts` as const);
+
+  return cursor === undefined
+    ? path
+    : (`${path}?cursor=${encodeURIComponent(cursor)}` as const);
+};
+
+// appends the first page, or the page after `cursor`
+const load = async (cursor?: string) => {
+  // hides the button until loaded, not to load the same page twice
+  next.value = null;
+
+  const res = await fetchAPI(timeline(cursor), "GET", null);
+
+  posts.value = [...posts.value, ...res.items];
+  next.value = res.next;
+};
+
 export default function () {
-  useEffect(() => {
-    fetchAPI("/posts", "GET", null).then((res) => {
-      posts.value = res;
-    });
-  }, []);
+  useEffect(() => void load(), []);
+
+  const cursor = next.value;
 
   return (
     <div class={`${root} ${apply}`}>
@@ -29,6 +52,13 @@ export default function () {
               <hr class={space} />
             </>
           ))}
+          {cursor !== null && (
+            <li class={elem}>
+              <button class={more} onClick={() => load(cursor)}>
+                More
+              </button>
+            </li>
+          )}
         </ul>
 
         <div class={f__k}>
@@ -3,12 +3,21 @@ import * as c from "./credential";
 
 const BASE_URL = import.meta.env.VITE_API_BASE_URL;
 
-export const register = async () => {
+export const register = async (name: string, invitation?: string) => {
   const url = BASE_URL + "/auth/register";
   const method = "POST";
   const headers = { "content-type": "application/json" };
 
-  const clg = await fetch(url, { headers, method, body: "null" });
+  const clg = await fetch(url, {
+    headers,
+    method,
+    body: JSON.stringify({ name, invitation }),
+  });
+
+  if (clg.status === 403) {
+    return "forbidden";
+  }
+
   if (clg.status !== 202) {
     throw new Error("failed to create challenge");
   }
@@ -44,12 +53,16 @@ export const register = async () => {
   }
 };
 
-export const claim = async () => {
+export const claim = async (name: string) => {
   const url = BASE_URL + "/auth/claim";
   const method = "POST";
   const headers = { "content-type": "application/json" };
 
-  const clg = await fetch(url, { headers, method, body: "null" });
+  const clg = await fetch(url, {
+    headers,
+    method,
+    body: JSON.stringify({ name }),
+  });
   if (clg.status === 200) {
     return "success";
   }
@@ -90,9 +103,9 @@ export const claim = async () => {
   }
 };
 
-export const refresh = async () => {
+const requestRefresh = async () => {
   const url = BASE_URL + "/auth/refresh";
-  const method = "GET";
+  const method = "POST";
 
   const res = await fetch(url, { method });
   switch (res.status) {
@@ -110,6 +123,19 @@ export const refresh = async () => {
   }
 };
 
+// shared by concurrent calls, since a refresh token can be used only once
+let refreshing: ReturnType<typeof requestRefresh> | null = null;
+
+export const refresh = () => {
+  if (refreshing === null) {
+    refreshing = requestRefresh().finally(() => {
+      refreshing = null;
+    });
+  }
+
+  return refreshing;
+};
+
 const Checked = z.object({
   refresh: z.boolean(),
   session: z.boolean(),
@@ -3,3 +3,6 @@ import { signal } from "@preact/signals";
 import { Post } from "./api";
 
 export const posts = signal<Post[]>([]);
+
+// cursor of the page after loaded posts, `null` if there's no more
+export const next = signal<string | null>(null);
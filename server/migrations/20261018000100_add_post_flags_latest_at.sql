@@ -0,0 +1,12 @@
+ALTER TABLE post_flags ADD COLUMN latest_at TIMESTAMP;
+
+DELETE FROM post_flags AS pf
+WHERE NOT EXISTS (SELECT 1 FROM posts WHERE id = pf.id);
+
+UPDATE post_flags AS pf
+SET latest_at = (SELECT MAX(created_at) FROM posts WHERE id = pf.id);
+
+ALTER TABLE post_flags ALTER COLUMN latest_at SET NOT NULL;
+
+CREATE INDEX post_flags_timeline ON post_flags (latest_at DESC, id DESC)
+WHERE is_deleted = FALSE;
@@ -0,0 +1,13 @@
+-- recover posts orphaned by a failed creation, as deleted (restorable) ones
+INSERT INTO post_flags (id, is_deleted, latest_at)
+SELECT id, TRUE, MAX(created_at) FROM posts AS p
+WHERE NOT EXISTS (SELECT 1 FROM post_flags WHERE id = p.id)
+GROUP BY id;
+
+ALTER TABLE posts ADD FOREIGN KEY (id) REFERENCES post_flags (id);
+
+CREATE SEQUENCE post_flags_id_seq OWNED BY post_flags.id;
+
+SELECT setval('post_flags_id_seq', COALESCE(MAX(id), 0) + 1, FALSE) FROM post_flags;
+
+ALTER TABLE post_flags ALTER COLUMN id SET DEFAULT nextval('post_flags_id_seq');
@@ -0,0 +1,20 @@
+CREATE TABLE users (
+    id         INT8      GENERATED BY DEFAULT AS IDENTITY,
+    name       TEXT      NOT NULL UNIQUE,
+    handle     BYTEA     NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- existing keys and posts are owned by the former single user
+INSERT INTO users (name, handle, created_at)
+SELECT 'owner', uuid_send(gen_random_uuid()), NOW() AT TIME ZONE 'UTC'
+WHERE EXISTS (SELECT 1 FROM keys) OR EXISTS (SELECT 1 FROM post_flags);
+
+ALTER TABLE keys ADD COLUMN user_id INT8 REFERENCES users (id);
+UPDATE keys SET user_id = (SELECT id FROM users WHERE name = 'owner');
+ALTER TABLE keys ALTER COLUMN user_id SET NOT NULL;
+
+ALTER TABLE post_flags ADD COLUMN author_id INT8 REFERENCES users (id);
+UPDATE post_flags SET author_id = (SELECT id FROM users WHERE name = 'owner');
+ALTER TABLE post_flags ALTER COLUMN author_id SET NOT NULL;
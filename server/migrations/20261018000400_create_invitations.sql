@@ -0,0 +1,8 @@
+CREATE TABLE invitations (
+    code       TEXT      NOT NULL,
+    issued_by  INT8      NOT NULL REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at    TIMESTAMP,
+    PRIMARY KEY (code)
+);
@@ -0,0 +1,10 @@
+ALTER TABLE keys
+    ADD COLUMN nickname     TEXT,
+    ADD COLUMN created_at   TIMESTAMP,
+    ADD COLUMN last_used_at TIMESTAMP,
+    -- filled on startup for existing rows, as it's only in `content`
+    ADD COLUMN cred_id      BYTEA UNIQUE;
+
+UPDATE keys SET created_at = NOW() AT TIME ZONE 'UTC';
+
+ALTER TABLE keys ALTER COLUMN created_at SET NOT NULL;
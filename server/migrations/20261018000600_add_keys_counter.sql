@@ -0,0 +1,2 @@
+-- signature counter last seen, `0` means the authenticator doesn't support it
+ALTER TABLE keys ADD COLUMN counter INT8 NOT NULL DEFAULT 0;
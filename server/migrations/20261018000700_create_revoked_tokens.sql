@@ -0,0 +1,8 @@
+CREATE TABLE revoked_tokens (
+    jti        TEXT      NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (jti)
+);
+
+-- tokens issued before this are revoked, by "log out everywhere"
+ALTER TABLE users ADD COLUMN tokens_revoked_at TIMESTAMP;
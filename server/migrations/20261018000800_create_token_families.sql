@@ -0,0 +1,9 @@
+-- refresh tokens rotated from one claim, only `current_jti` of them is usable
+CREATE TABLE token_families (
+    id          TEXT      NOT NULL,
+    user_id     INT8      NOT NULL REFERENCES users (id),
+    current_jti TEXT      NOT NULL,
+    expires_at  TIMESTAMP NOT NULL,
+    revoked_at  TIMESTAMP,
+    PRIMARY KEY (id)
+);
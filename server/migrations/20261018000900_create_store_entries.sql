@@ -0,0 +1,10 @@
+-- values of `stores::PgStore`, e.g. states of webauthn ceremonies
+CREATE TABLE store_entries (
+    kind       TEXT      NOT NULL,
+    key        TEXT      NOT NULL,
+    value      BYTEA     NOT NULL,
+    expires_at TIMESTAMP,
+    PRIMARY KEY (kind, key)
+);
+
+CREATE INDEX store_entries_expires_at ON store_entries (expires_at);
@@ -0,0 +1,5 @@
+-- `simple` doesn't stem, posts are written in various languages
+ALTER TABLE posts ADD COLUMN search TSVECTOR
+    GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+CREATE INDEX posts_search ON posts USING GIN (search);
@@ -0,0 +1,11 @@
+-- hashtags of latest revisions
+CREATE TABLE post_tags (
+    id  INT8 NOT NULL REFERENCES post_flags (id),
+    tag TEXT NOT NULL,
+    PRIMARY KEY (id, tag)
+);
+
+CREATE INDEX post_tags_tag ON post_tags (tag);
+
+-- revision whose tags are in `post_tags`, existing posts are filled on startup
+ALTER TABLE post_flags ADD COLUMN tagged_at TIMESTAMP;
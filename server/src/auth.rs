@@ -1,13 +1,13 @@
+mod keyset;
+
 use serde::{Deserialize, Serialize};
 
-macro eval_once($ty:path, $expr:expr) {{
-    static CACHE: std::sync::LazyLock<$ty> = std::sync::LazyLock::new(|| $expr);
+use crate::config::Config;
 
-    &*CACHE
-}}
+pub use keyset::Keyset;
 
 #[derive(Serialize, Deserialize)]
-#[serde(tag = "sub", rename_all = "camelCase")]
+#[serde(tag = "kind", rename_all = "camelCase")]
 pub enum Token {
     Refresh {
         #[serde(flatten)]
@@ -20,7 +20,7 @@ pub enum Token {
 }
 
 impl Token {
-    pub fn sub(&self) -> &'static str {
+    pub fn kind(&self) -> &'static str {
         match self {
             Self::Refresh { .. } => "refresh",
             Self::Session { .. } => "session",
@@ -33,34 +33,12 @@ impl Token {
 }
 
 impl Token {
-    pub fn encode(&self) -> anyhow::Result<String> {
-        use jsonwebtoken as jwt;
-
-        let key = eval_once!(jwt::EncodingKey, {
-            use base64::prelude::{Engine, BASE64_STANDARD as engine};
-
-            let raw = engine.decode(*crate::vars::JWT_ENC_KEY).unwrap();
-            jwt::EncodingKey::from_ed_der(&raw)
-        });
-
-        let header = eval_once!(jwt::Header, {
-            jwt::Header::new(jwt::Algorithm::EdDSA) //
-        });
-
-        Ok(jwt::encode(header, self, key)?)
-    }
+    pub fn encode(&self, keys: &Keyset) -> anyhow::Result<String> { keys.encode(self) }
 
-    pub fn decode(token: &str) -> anyhow::Result<Self> {
+    pub fn decode(token: &str, keys: &Keyset, config: &Config) -> anyhow::Result<Self> {
         use jsonwebtoken as jwt;
 
-        let key = eval_once!(jwt::DecodingKey, {
-            use base64::prelude::{Engine, BASE64_STANDARD as engine};
-
-            let raw = engine.decode(*crate::vars::JWT_DEC_KEY).unwrap();
-            jwt::DecodingKey::from_ed_der(&raw)
-        });
-
-        let validation = eval_once!(jwt::Validation, {
+        let validation = {
             let mut v = jwt::Validation::new(jwt::Algorithm::EdDSA);
 
             let fields = ["iss", "sub", "aud", "exp", "iat", "jti"]
@@ -70,32 +48,65 @@ impl Token {
             v.required_spec_claims.extend(fields);
 
             v.set_audience(&["client"]);
-            v.set_issuer(&[*crate::vars::SERVE_HOST]);
+            v.set_issuer(&[&config.serve_host]);
 
             v
-        });
+        };
 
-        let data = jwt::decode::<Self>(token, key, validation)?;
+        let token = keys.decode::<Self>(token, &validation)?;
 
-        if data.claims.iat > NumericDate::now() {
+        if token.iat > NumericDate::now() {
             anyhow::bail!("token is issued in the future?!");
         }
 
-        Ok(data.claims)
+        Ok(token)
     }
 }
 
 impl Token {
-    pub fn issue_refresh() -> Self {
-        Self::Refresh {
-            inner: Claims::new(core::time::Duration::from_secs(60 * 60 * 24 * 4)),
-        }
+    /// issues a refresh token starting a new family
+    pub fn issue_refresh(config: &Config, user_id: i64, auth: Authentication) -> Self {
+        use webauthn_rs::prelude::Uuid;
+
+        Self::issue_refresh_in(config, user_id, auth, Uuid::new_v4().to_string())
     }
 
-    pub fn issue_session() -> Self {
-        Self::Session {
-            inner: Claims::new(core::time::Duration::from_secs(60 * 60)),
-        }
+    fn issue_refresh_in(
+        config: &Config,
+        user_id: i64,
+        auth: Authentication,
+        family: String,
+    ) -> Self {
+        let duration = config.tokens.refresh_lifetime;
+
+        let mut inner = Claims::new(config, user_id, auth, duration);
+        inner.fam = Some(family);
+
+        Self::Refresh { inner }
+    }
+
+    /// next refresh token in the same family, `None` if this isn't a refresh token of any family
+    pub fn rotate(&self, config: &Config) -> anyhow::Result<Option<Self>> {
+        let Self::Refresh { inner } = self else {
+            return Ok(None);
+        };
+
+        let Some(ref family) = inner.fam else {
+            return Ok(None);
+        };
+
+        let (user_id, auth) = (inner.user_id()?, inner.auth.clone());
+
+        Ok(Some(Self::issue_refresh_in(config, user_id, auth, family.clone())))
+    }
+
+    /// issues a session token, authenticated in the same way as this token
+    pub fn issue_session(&self, config: &Config) -> anyhow::Result<Self> {
+        let duration = config.tokens.session_lifetime;
+
+        Ok(Self::Session {
+            inner: Claims::new(config, self.user_id()?, self.auth.clone(), duration),
+        })
     }
 }
 
@@ -130,6 +141,18 @@ impl NumericDate {
     }
 
     pub fn after_secs(&self, secs: u64) -> Self { Self(self.0 + secs) }
+
+    /// seconds since the epoch
+    pub fn as_secs(&self) -> u64 { self.0 }
+}
+
+impl From<NumericDate> for chrono::NaiveDateTime {
+    fn from(date: NumericDate) -> Self {
+        i64::try_from(date.0)
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map_or(Self::MAX, |d| d.naive_utc())
+    }
 }
 
 impl Serialize for NumericDate {
@@ -147,25 +170,83 @@ impl<'de> Deserialize<'de> for NumericDate {
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     iss: String,
+    sub: String,
     aud: String,
     exp: NumericDate,
     iat: NumericDate,
     jti: String,
+    #[serde(flatten)]
+    auth: Authentication,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fam: Option<String>,
 }
 
 impl Claims {
-    fn new(duration: core::time::Duration) -> Self {
+    fn new(
+        config: &Config,
+        user_id: i64,
+        auth: Authentication,
+        duration: core::time::Duration,
+    ) -> Self {
         use webauthn_rs::prelude::Uuid;
 
         let (from, to) = available_time(duration);
         let uuid = Uuid::new_v4().to_string();
 
         Self {
-            iss: crate::vars::SERVE_HOST.to_owned(),
+            iss: config.serve_host.clone(),
+            sub: user_id.to_string(),
             aud: "client".to_owned(),
             exp: to,
             iat: from,
             jti: uuid,
+            auth,
+            fam: None,
+        }
+    }
+}
+
+impl Claims {
+    /// user who this token is issued for
+    pub fn user_id(&self) -> anyhow::Result<i64> { Ok(self.sub.parse()?) }
+
+    pub fn jti(&self) -> &str { &self.jti }
+
+    pub fn issued_at(&self) -> NumericDate { self.iat }
+
+    pub fn expires_at(&self) -> NumericDate { self.exp }
+
+    /// family of refresh tokens which this token belongs to
+    pub fn family(&self) -> Option<&str> { self.fam.as_deref() }
+
+    /// passkey which the user is authenticated with
+    pub fn key_id(&self) -> u32 { self.auth.cid }
+
+    pub fn authenticated_at(&self) -> NumericDate { self.auth.auth_time }
+}
+
+/// how the user is authenticated, carried over to every token issued from the authentication
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Authentication {
+    /// id of the passkey
+    cid: u32,
+    /// methods, as values of RFC 8176
+    amr: Vec<String>,
+    auth_time: NumericDate,
+}
+
+impl Authentication {
+    pub fn with_passkey(key_id: u32, user_verified: bool) -> Self {
+        let mut amr = vec!["pop".to_owned()];
+
+        if user_verified {
+            amr.push("user".to_owned());
+        }
+
+        Self {
+            cid: key_id,
+            amr,
+            auth_time: NumericDate::now(),
         }
     }
 }
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use jsonwebtoken as jwt;
+
+/// keys for signing / verifying tokens, identified by `kid`
+///
+/// loaded from a directory which has `<kid>.pub` (base64 of public key) for each key, and
+/// `<kid>.key` (base64 of PKCS#8 document) for keys which can sign. the greatest `kid` among
+/// keys which can sign is used for signing, so rotating is done by adding a newer key, and
+/// removing the older one after all tokens signed by it are expired.
+pub struct Keyset {
+    dir: Option<PathBuf>,
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    signing: (String, jwt::EncodingKey),
+    verifying: HashMap<String, Verifying>,
+}
+
+struct Verifying {
+    key: jwt::DecodingKey,
+    raw: Vec<u8>,
+}
+
+impl Keyset {
+    /// kid of the key given by `from_pair`
+    pub const DEFAULT_KID: &'static str = "default";
+
+    pub fn load(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        let inner = Inner::load(&dir)?;
+
+        Ok(Self {
+            dir: Some(dir),
+            inner: RwLock::new(inner),
+        })
+    }
+
+    /// keyset of single key, which can't be reloaded
+    pub fn from_pair(enc: &str, dec: &str) -> anyhow::Result<Self> {
+        let (enc, dec) = (decode_base64(enc)?, decode_base64(dec)?);
+
+        let inner = Inner {
+            signing: (Self::DEFAULT_KID.to_owned(), jwt::EncodingKey::from_ed_der(&enc)),
+            verifying: HashMap::from([(Self::DEFAULT_KID.to_owned(), Verifying::new(dec))]),
+        };
+
+        Ok(Self {
+            dir: None,
+            inner: RwLock::new(inner),
+        })
+    }
+
+    /// loads keys from the directory again, keeps current keys if failed
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+
+        let inner = Inner::load(dir)?;
+        *self.write() = inner;
+
+        Ok(())
+    }
+
+    pub fn encode<T: serde::Serialize>(&self, claims: &T) -> anyhow::Result<String> {
+        // not to hold the lock while signing
+        let (kid, key) = self.read().signing.clone();
+
+        let mut header = jwt::Header::new(jwt::Algorithm::EdDSA);
+        header.kid = Some(kid);
+
+        Ok(jwt::encode(&header, claims, &key)?)
+    }
+
+    pub fn decode<T: serde::de::DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &jwt::Validation,
+    ) -> anyhow::Result<T> {
+        let header = jwt::decode_header(token)?;
+        let kid = header.kid.ok_or_else(|| anyhow::anyhow!("token has no kid"))?;
+
+        let key = self
+            .read()
+            .verifying
+            .get(&kid)
+            .map(|v| v.key.clone())
+            .ok_or_else(|| anyhow::anyhow!("key of kid {kid:?} isn't found"))?;
+
+        Ok(jwt::decode::<T>(token, &key, validation)?.claims)
+    }
+
+    /// pairs of kid and raw public key, of all keys for verifying
+    pub fn public_keys(&self) -> Vec<(String, Vec<u8>)> {
+        let mut keys = self
+            .read()
+            .verifying
+            .iter()
+            .map(|(kid, v)| (kid.clone(), v.raw.clone()))
+            .collect::<Vec<_>>();
+
+        keys.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        keys
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Inner {
+    fn load(dir: &std::path::Path) -> anyhow::Result<Self> {
+        let mut signing = None::<(String, Vec<u8>)>;
+        let mut verifying = HashMap::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            let (Some(kid), Some(ext)) = (path.file_stem(), path.extension()) else {
+                continue;
+            };
+
+            let kid = kid
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("kid of {} isn't utf-8", path.display()))?
+                .to_owned();
+
+            let raw = decode_base64(&std::fs::read_to_string(&path)?)?;
+
+            match ext.to_str() {
+                Some("pub") => {
+                    verifying.insert(kid, Verifying::new(raw));
+                },
+                // the greatest kid is used for signing
+                Some("key") if signing.as_ref().is_none_or(|(k, _)| *k < kid) => {
+                    signing.replace((kid, raw));
+                },
+                _ => {},
+            }
+        }
+
+        let (kid, raw) = signing
+            .ok_or_else(|| anyhow::anyhow!("no key for signing in {}", dir.display()))?;
+
+        if !verifying.contains_key(&kid) {
+            anyhow::bail!("public key of kid {kid:?} isn't found in {}", dir.display());
+        }
+
+        Ok(Self {
+            signing: (kid, jwt::EncodingKey::from_ed_der(&raw)),
+            verifying,
+        })
+    }
+}
+
+impl Verifying {
+    fn new(raw: Vec<u8>) -> Self {
+        Self {
+            key: jwt::DecodingKey::from_ed_der(&raw),
+            raw,
+        }
+    }
+}
+
+fn decode_base64(s: &str) -> anyhow::Result<Vec<u8>> {
+    use base64::prelude::{Engine, BASE64_STANDARD as engine};
+
+    Ok(engine.decode(s.trim())?)
+}
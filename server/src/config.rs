@@ -0,0 +1,434 @@
+use core::time::Duration;
+use std::path::PathBuf;
+
+use actix_web::cookie::SameSite;
+
+/// configuration of server, loaded and validated at startup
+pub struct Config {
+    pub listen_addr: String,
+    /// host serving this, used as RP ID of webauthn, issuer of tokens and domain of cookies
+    pub serve_host: String,
+    pub db_url: String,
+    pub jwt_keys: JwtKeys,
+    pub tokens: Tokens,
+    pub cookies: Cookies,
+    pub cors: Cors,
+    pub limits: Limits,
+    /// `CEREMONY_STORE`, one of `postgres` and `memory` (default: `postgres`)
+    pub ceremony_store: CeremonyStore,
+}
+
+pub enum JwtKeys {
+    /// directory of keyset, see `auth::Keyset`
+    Keyset(PathBuf),
+    /// pair of base64 keys, as `JWT_ENC_KEY` / `JWT_DEC_KEY`
+    Pair { enc: String, dec: String },
+}
+
+pub struct Tokens {
+    /// `REFRESH_LIFETIME`, in seconds (default: 4 days)
+    pub refresh_lifetime: Duration,
+    /// `SESSION_LIFETIME`, in seconds (default: 1 hour)
+    pub session_lifetime: Duration,
+}
+
+pub struct Cookies {
+    /// `COOKIE_DOMAIN` (default: `SERVE_HOST`), empty for host-only cookies
+    pub domain: Option<String>,
+    /// `COOKIE_PATH` (default: `/-api/`)
+    pub path: String,
+    /// `COOKIE_SECURE` (default: `true`)
+    pub secure: bool,
+    /// `COOKIE_SAME_SITE`, one of `strict`, `lax` and `none` (default: `strict`)
+    pub same_site: SameSite,
+}
+
+/// CORS policy, credentials are allowed for these origins only
+pub struct Cors {
+    /// `CORS_ORIGINS`, comma separated (default: `https://` + `SERVE_HOST`)
+    pub origins: Vec<String>,
+    /// `CORS_METHODS`, comma separated (default: `GET,POST,PATCH,DELETE`)
+    pub methods: Vec<String>,
+    /// `CORS_HEADERS`, comma separated (default: `content-type`)
+    pub headers: Vec<String>,
+}
+
+/// rate limits of state-changing requests, `0` disables the limit
+pub struct Limits {
+    /// `TRUST_PROXY`, whether to use `Forwarded` / `X-Forwarded-For` as IP (default: `false`)
+    pub trust_proxy: bool,
+    /// `RATE_LIMIT_AUTH_PER_IP` / `RATE_LIMIT_AUTH_GLOBAL`, per minute (default: 10 / 100)
+    pub auth: Limit,
+    /// `RATE_LIMIT_POSTS_PER_IP` / `RATE_LIMIT_POSTS_GLOBAL`, per minute (default: 5 / 30)
+    pub posts: Limit,
+}
+
+#[derive(Clone, Copy)]
+pub struct Limit {
+    pub per_ip: u32,
+    pub global: u32,
+}
+
+/// where states of webauthn ceremonies are kept
+#[derive(Clone, Copy)]
+pub enum CeremonyStore {
+    /// shared by instances, and kept across restarting
+    Postgres,
+    /// only for single instance, lost by restarting
+    Memory,
+}
+
+/// comma separated values in environment variables, array in the TOML file
+#[derive(serde::Deserialize)]
+#[serde(transparent)]
+struct List(Vec<String>);
+
+impl core::str::FromStr for List {
+    type Err = core::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+
+        Ok(Self(values))
+    }
+}
+
+/// values of configuration, before validation
+///
+/// each of them is read from environment variable of upper case (e.g. `SERVE_HOST`), or the
+/// TOML file if it's unset
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Raw {
+    listen_addr: Option<String>,
+    serve_host: Option<String>,
+    db_url: Option<String>,
+    jwt_keyset: Option<PathBuf>,
+    jwt_enc_key: Option<String>,
+    jwt_dec_key: Option<String>,
+    refresh_lifetime: Option<u64>,
+    session_lifetime: Option<u64>,
+    cookie_domain: Option<String>,
+    cookie_path: Option<String>,
+    cookie_secure: Option<bool>,
+    cookie_same_site: Option<String>,
+    cors_origins: Option<List>,
+    cors_methods: Option<List>,
+    cors_headers: Option<List>,
+    trust_proxy: Option<bool>,
+    rate_limit_auth_per_ip: Option<u32>,
+    rate_limit_auth_global: Option<u32>,
+    rate_limit_posts_per_ip: Option<u32>,
+    rate_limit_posts_global: Option<u32>,
+    ceremony_store: Option<String>,
+}
+
+impl Config {
+    /// TOML file, used if `CONFIG_FILE` is unset and it exists
+    pub const DEFAULT_FILE: &'static str = "zinkin.toml";
+
+    /// loads from environment variables, `.env` and the TOML file, reports all problems at once
+    pub fn load() -> anyhow::Result<Self> {
+        match dotenvy::dotenv_override() {
+            Ok(_) => {},
+            Err(e) if e.not_found() => {},
+            Err(e) => anyhow::bail!("failed to load .env: {e}"),
+        }
+
+        let file = std::env::var_os("CONFIG_FILE").map_or_else(
+            || Some(PathBuf::from(Self::DEFAULT_FILE)).filter(|p| p.exists()),
+            |path| Some(PathBuf::from(path)),
+        );
+
+        let raw = match file {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))?;
+
+                toml::from_str(&text)
+                    .map_err(|e| anyhow::anyhow!("failed to parse {}: {e}", path.display()))?
+            },
+            None => Raw::default(),
+        };
+
+        let mut problems = vec![];
+        let raw = raw.overlay_env(&mut problems);
+        let config = raw.validate(&mut problems);
+
+        match config {
+            Some(config) if problems.is_empty() => Ok(config),
+            _ => anyhow::bail!("invalid configuration:\n  - {}", problems.join("\n  - ")),
+        }
+    }
+}
+
+impl Raw {
+    fn overlay_env(self, problems: &mut Vec<String>) -> Self {
+        Self {
+            listen_addr: var("LISTEN_ADDR", problems).or(self.listen_addr),
+            serve_host: var("SERVE_HOST", problems).or(self.serve_host),
+            db_url: var("DB_URL", problems).or(self.db_url),
+            jwt_keyset: var("JWT_KEYSET", problems).or(self.jwt_keyset),
+            jwt_enc_key: var("JWT_ENC_KEY", problems).or(self.jwt_enc_key),
+            jwt_dec_key: var("JWT_DEC_KEY", problems).or(self.jwt_dec_key),
+            refresh_lifetime: var("REFRESH_LIFETIME", problems).or(self.refresh_lifetime),
+            session_lifetime: var("SESSION_LIFETIME", problems).or(self.session_lifetime),
+            cookie_domain: var("COOKIE_DOMAIN", problems).or(self.cookie_domain),
+            cookie_path: var("COOKIE_PATH", problems).or(self.cookie_path),
+            cookie_secure: var("COOKIE_SECURE", problems).or(self.cookie_secure),
+            cookie_same_site: var("COOKIE_SAME_SITE", problems).or(self.cookie_same_site),
+            cors_origins: var("CORS_ORIGINS", problems).or(self.cors_origins),
+            cors_methods: var("CORS_METHODS", problems).or(self.cors_methods),
+            cors_headers: var("CORS_HEADERS", problems).or(self.cors_headers),
+            trust_proxy: var("TRUST_PROXY", problems).or(self.trust_proxy),
+            rate_limit_auth_per_ip: var("RATE_LIMIT_AUTH_PER_IP", problems)
+                .or(self.rate_limit_auth_per_ip),
+            rate_limit_auth_global: var("RATE_LIMIT_AUTH_GLOBAL", problems)
+                .or(self.rate_limit_auth_global),
+            rate_limit_posts_per_ip: var("RATE_LIMIT_POSTS_PER_IP", problems)
+                .or(self.rate_limit_posts_per_ip),
+            rate_limit_posts_global: var("RATE_LIMIT_POSTS_GLOBAL", problems)
+                .or(self.rate_limit_posts_global),
+            ceremony_store: var("CEREMONY_STORE", problems).or(self.ceremony_store),
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) -> Option<Config> {
+        let mut required = |name: &str, value: Option<String>| {
+            if value.is_none() {
+                problems.push(format!("{name} is missing"));
+            }
+
+            value
+        };
+
+        let listen_addr = required("LISTEN_ADDR", self.listen_addr.clone());
+        let serve_host = required("SERVE_HOST", self.serve_host.clone());
+        let db_url = required("DB_URL", self.db_url.clone());
+
+        if let Some(ref addr) = listen_addr {
+            use std::net::ToSocketAddrs;
+
+            if let Err(e) = addr.to_socket_addrs() {
+                problems.push(format!("LISTEN_ADDR is invalid: {e}"));
+            }
+        }
+
+        if let Some(ref host) = serve_host {
+            match url::Url::parse(&format!("https://{host}")) {
+                Ok(url) if url.host_str() == Some(host) => {},
+                Ok(_) => problems.push("SERVE_HOST must be a bare host".to_owned()),
+                Err(e) => problems.push(format!("SERVE_HOST is invalid: {e}")),
+            }
+        }
+
+        if let Some(ref url) = db_url {
+            match url::Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "postgres" | "postgresql") => {},
+                Ok(_) => problems.push("DB_URL must be a url of postgresql".to_owned()),
+                Err(e) => problems.push(format!("DB_URL is invalid: {e}")),
+            }
+        }
+
+        let jwt_keys = self.validate_jwt(problems);
+        let tokens = self.validate_tokens(problems);
+        let cookies = self.validate_cookies(serve_host.as_deref(), problems);
+        let cors = self.validate_cors(serve_host.as_deref(), problems);
+        let limits = self.validate_limits();
+        let ceremony_store = self.validate_ceremony_store(problems);
+
+        Some(Config {
+            listen_addr: listen_addr?,
+            serve_host: serve_host?,
+            db_url: db_url?,
+            jwt_keys: jwt_keys?,
+            tokens,
+            cookies: cookies?,
+            cors,
+            limits,
+            ceremony_store: ceremony_store?,
+        })
+    }
+
+    fn validate_jwt(&self, problems: &mut Vec<String>) -> Option<JwtKeys> {
+        match (&self.jwt_keyset, &self.jwt_enc_key, &self.jwt_dec_key) {
+            (Some(dir), _, _) => {
+                if !dir.is_dir() {
+                    problems.push(format!("JWT_KEYSET {} isn't a directory", dir.display()));
+                }
+
+                Some(JwtKeys::Keyset(dir.clone()))
+            },
+            (None, Some(enc), Some(dec)) => {
+                use base64::prelude::{Engine, BASE64_STANDARD as engine};
+
+                for (name, key) in [("JWT_ENC_KEY", enc), ("JWT_DEC_KEY", dec)] {
+                    if let Err(e) = engine.decode(key.trim()) {
+                        problems.push(format!("{name} isn't valid base64: {e}"));
+                    }
+                }
+
+                Some(JwtKeys::Pair {
+                    enc: enc.clone(),
+                    dec: dec.clone(),
+                })
+            },
+            (None, _, _) => {
+                let message = "JWT_KEYSET, or both of JWT_ENC_KEY and JWT_DEC_KEY are missing";
+                problems.push(message.to_owned());
+                None
+            },
+        }
+    }
+
+    fn validate_tokens(&self, problems: &mut Vec<String>) -> Tokens {
+        let tokens = Tokens {
+            refresh_lifetime: Duration::from_secs(self.refresh_lifetime.unwrap_or(345_600)),
+            session_lifetime: Duration::from_secs(self.session_lifetime.unwrap_or(3_600)),
+        };
+
+        for (name, lifetime) in [
+            ("REFRESH_LIFETIME", tokens.refresh_lifetime),
+            ("SESSION_LIFETIME", tokens.session_lifetime),
+        ] {
+            if lifetime.is_zero() {
+                problems.push(format!("{name} must be positive"));
+            }
+        }
+
+        tokens
+    }
+
+    fn validate_cookies(
+        &self,
+        serve_host: Option<&str>,
+        problems: &mut Vec<String>,
+    ) -> Option<Cookies> {
+        let same_site = match self.cookie_same_site.as_deref() {
+            None | Some("strict") => Some(SameSite::Strict),
+            Some("lax") => Some(SameSite::Lax),
+            Some("none") => Some(SameSite::None),
+            Some(other) => {
+                problems.push(format!("COOKIE_SAME_SITE is invalid: {other:?}"));
+                None
+            },
+        };
+
+        let secure = self.cookie_secure.unwrap_or(true);
+
+        // browsers reject `SameSite=None` without `Secure`
+        if same_site == Some(SameSite::None) && !secure {
+            problems.push("COOKIE_SAME_SITE=none requires COOKIE_SECURE=true".to_owned());
+        }
+
+        let domain = self.cookie_domain.as_ref().map_or_else(
+            || serve_host.map(ToOwned::to_owned),
+            |d| Some(d.clone()).filter(|d| !d.is_empty()),
+        );
+
+        Some(Cookies {
+            domain,
+            path: self.cookie_path.clone().unwrap_or_else(|| "/-api/".to_owned()),
+            secure,
+            same_site: same_site?,
+        })
+    }
+
+    fn validate_cors(&self, serve_host: Option<&str>, problems: &mut Vec<String>) -> Cors {
+        let list = |list: &Option<List>, default: Vec<String>| {
+            list.as_ref().map_or(default, |List(values)| values.clone())
+        };
+
+        let cors = Cors {
+            origins: list(
+                &self.cors_origins,
+                serve_host.iter().map(|h| format!("https://{h}")).collect(),
+            ),
+            methods: list(
+                &self.cors_methods,
+                ["GET", "POST", "PATCH", "DELETE"].map(ToOwned::to_owned).to_vec(),
+            ),
+            headers: list(&self.cors_headers, vec!["content-type".to_owned()]),
+        };
+
+        for origin in &cors.origins {
+            // `*` can't be used with credentials
+            let is_origin = url::Url::parse(origin).is_ok_and(|url| {
+                let parsed = url.origin();
+                parsed.is_tuple() && parsed.ascii_serialization() == *origin
+            });
+
+            if !is_origin {
+                problems.push(format!("CORS_ORIGINS has invalid origin: {origin:?}"));
+            }
+        }
+
+        for method in &cors.methods {
+            if actix_web::http::Method::from_bytes(method.as_bytes()).is_err() {
+                problems.push(format!("CORS_METHODS has invalid method: {method:?}"));
+            }
+        }
+
+        for header in &cors.headers {
+            if actix_web::http::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                problems.push(format!("CORS_HEADERS has invalid header: {header:?}"));
+            }
+        }
+
+        cors
+    }
+
+    fn validate_limits(&self) -> Limits {
+        Limits {
+            trust_proxy: self.trust_proxy.unwrap_or(false),
+            auth: Limit {
+                per_ip: self.rate_limit_auth_per_ip.unwrap_or(10),
+                global: self.rate_limit_auth_global.unwrap_or(100),
+            },
+            posts: Limit {
+                per_ip: self.rate_limit_posts_per_ip.unwrap_or(5),
+                global: self.rate_limit_posts_global.unwrap_or(30),
+            },
+        }
+    }
+
+    fn validate_ceremony_store(&self, problems: &mut Vec<String>) -> Option<CeremonyStore> {
+        match self.ceremony_store.as_deref() {
+            None | Some("postgres") => Some(CeremonyStore::Postgres),
+            Some("memory") => Some(CeremonyStore::Memory),
+            Some(other) => {
+                problems.push(format!("CEREMONY_STORE is invalid: {other:?}"));
+                None
+            },
+        }
+    }
+}
+
+/// reads environment variable, `None` if it's unset or invalid
+fn var<T>(name: &str, problems: &mut Vec<String>) -> Option<T>
+where
+    T: core::str::FromStr,
+    T::Err: core::fmt::Display,
+{
+    let value = match std::env::var(name) {
+        Ok(value) => value,
+        Err(std::env::VarError::NotPresent) => return None,
+        Err(std::env::VarError::NotUnicode(_)) => {
+            problems.push(format!("{name} isn't valid unicode"));
+            return None;
+        },
+    };
+
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            problems.push(format!("{name} is invalid: {e}"));
+            None
+        },
+    }
+}
@@ -40,38 +40,85 @@ pub mod stores;
 /// define about authn / authz
 pub mod auth;
 
-pub mod vars {
-    macro static_lazy($name:ident, $expr:expr) {
-        pub static $name: std::sync::LazyLock<&str> = std::sync::LazyLock::new(|| $expr);
-    }
-
-    macro load_env($name:ident) {
-        static_lazy!($name, std::env::var(stringify!($name)).unwrap().leak());
-    }
-
-    load_env!(LISTEN_ADDR);
-    load_env!(SERVE_HOST);
-
-    load_env!(DB_URL);
+/// generates keys for signing tokens, as `zinkin keygen`
+pub mod keygen;
 
-    load_env!(JWT_ENC_KEY);
-    load_env!(JWT_DEC_KEY);
-}
+/// defines configuration of server
+pub mod config;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv_override().ok().unwrap();
+    let mut args = std::env::args().skip(1);
+
+    // subcommands are run without .env
+    if let Some(command) = args.next() {
+        return match &*command {
+            "keygen" => keygen::run(args),
+            _ => anyhow::bail!("unknown subcommand: {command}"),
+        };
+    }
+
+    let config = actix_web::web::Data::new(config::Config::load()?);
 
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
         .pretty()
         .init();
 
-    let repo = actix_web::web::Data::new(repos::PgRepository::new(*vars::DB_URL).await?);
-    let store = actix_web::web::Data::new(stores::InMemoryStore::<routes::SessionId>::new());
+    let repo = actix_web::web::Data::new(repos::PgRepository::new(&config.db_url).await?);
+    let pool: &sqlx::PgPool = &repo;
+    let registrations = actix_web::web::Data::new(ceremony_store::<routes::Registration>(
+        config.ceremony_store,
+        pool,
+        "registration",
+    ));
+    let authentications = actix_web::web::Data::new(ceremony_store::<
+        webauthn_rs::prelude::PasskeyAuthentication,
+    >(config.ceremony_store, pool, "authentication"));
+
+    // sweeps states of abandoned ceremonies
+    tokio::spawn({
+        let (registrations, authentications) = (registrations.clone(), authentications.clone());
+
+        async move {
+            let mut interval = tokio::time::interval(core::time::Duration::from_mins(1));
+
+            loop {
+                interval.tick().await;
+
+                for result in [registrations.sweep().await, authentications.sweep().await] {
+                    if let Err(any) = result {
+                        tracing::error!(%any, "failed to sweep store");
+                    }
+                }
+            }
+        }
+    });
+
+    let keys = actix_web::web::Data::new(match config.jwt_keys {
+        config::JwtKeys::Keyset(ref dir) => auth::Keyset::load(dir)?,
+        config::JwtKeys::Pair { ref enc, ref dec } => auth::Keyset::from_pair(enc, dec)?,
+    });
+
+    // reloads keyset by SIGHUP, to rotate keys without restarting
+    tokio::spawn({
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let keys = keys.clone();
+        let mut hangup = signal(SignalKind::hangup())?;
+
+        async move {
+            while hangup.recv().await.is_some() {
+                match keys.reload() {
+                    Ok(()) => tracing::info!("reloaded keyset"),
+                    Err(any) => tracing::error!(%any, "failed to reload keyset"),
+                }
+            }
+        }
+    });
 
     let site = actix_web::web::Data::new({
-        let url = webauthn_rs::prelude::Url::parse(&format!("https://{}", *vars::SERVE_HOST))?;
+        let url = webauthn_rs::prelude::Url::parse(&format!("https://{}", config.serve_host))?;
         let host = url
             .host_str()
             .ok_or_else(|| anyhow::anyhow!("hostname is none"))?;
@@ -79,31 +126,58 @@ async fn main() -> anyhow::Result<()> {
         webauthn_rs::WebauthnBuilder::new(host, &url)?.build()?
     });
 
+    let limiters = actix_web::web::Data::new(routes::Limiters::new(&config.limits));
+
+    let listen_addr = config.listen_addr.clone();
+
     actix_web::HttpServer::new(move || {
-        let cors = actix_cors::Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
-            .expose_any_header()
+        let cors = config
+            .cors
+            .origins
+            .iter()
+            .fold(actix_cors::Cors::default(), |cors, o| cors.allowed_origin(o))
+            .allowed_methods(config.cors.methods.iter().map(String::as_str))
+            .allowed_headers(config.cors.headers.iter().map(String::as_str))
+            .supports_credentials()
             .max_age(3600);
 
         actix_web::App::new()
             .app_data(repo.clone())
-            .app_data(store.clone())
+            .app_data(registrations.clone())
+            .app_data(authentications.clone())
             .app_data(site.clone())
+            .app_data(keys.clone())
+            .app_data(config.clone())
+            .app_data(limiters.clone())
             .wrap(tracing_actix_web::TracingLogger::default())
             .wrap(cors)
             .wrap(actix_web::middleware::NormalizePath::trim())
             .service(routes::services::<
                 repos::PgRepository,
                 repos::PgRepository,
-                stores::InMemoryStore<_>,
-                stores::InMemoryStore<_>,
+                stores::EitherStore<_, _>,
+                stores::EitherStore<_, _>,
+                repos::PgRepository,
             >())
     })
-    .bind(*vars::LISTEN_ADDR)?
+    .bind(listen_addr)?
     .run()
     .await?;
 
     Ok(())
 }
+
+/// store of states of ceremonies, `kind` is used only for postgres
+fn ceremony_store<T>(
+    store: config::CeremonyStore,
+    pool: &sqlx::PgPool,
+    kind: &'static str,
+) -> stores::EitherStore<routes::SessionId, T> {
+    match store {
+        // shared by instances, ceremonies aren't broken by restarting or by switching instances
+        config::CeremonyStore::Postgres => {
+            stores::EitherStore::Pg(stores::PgStore::new(pool.clone(), kind))
+        },
+        config::CeremonyStore::Memory => stores::EitherStore::InMemory(stores::InMemoryStore::new()),
+    }
+}
@@ -14,7 +14,8 @@ pub trait FromModel {
 pub type DateTime = chrono::NaiveDateTime;
 
 pub struct Post {
-    pub id: u32,
+    pub id: i64,
+    pub author_id: i64,
     pub content: String,
     pub posted_at: DateTime,
     pub created_at: DateTime,
@@ -22,13 +23,276 @@ pub struct Post {
 }
 
 impl Post {
-    pub fn new(id: u32, content: String, now: DateTime) -> Self {
-        Self {
-            id,
-            content,
-            posted_at: now,
-            created_at: now,
-            is_deleted: false,
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+
+    /// hashtags in `content`, normalized and deduplicated
+    pub fn tags(&self) -> Vec<String> {
+        markdown(&self.content)
+            .filter_map(|f| match f {
+                Fragment::Hashtag { tag, .. } => Some(tag),
+                Fragment::Event(_) => None,
+            })
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// piece of markdown, hashtags are split out of text
+pub enum Fragment<'a> {
+    Event(pulldown_cmark::Event<'a>),
+    /// `text` is as written (e.g. `#Rust`), `tag` is normalized (e.g. `rust`)
+    Hashtag { text: String, tag: String },
+}
+
+/// parses markdown of post, hashtags aren't found in code or in links
+pub fn markdown(content: &str) -> impl Iterator<Item = Fragment<'_>> {
+    use pulldown_cmark::{Event, Parser, Tag, TagEnd, TextMergeStream};
+
+    let mut depth = 0_usize;
+
+    TextMergeStream::new(Parser::new(content)).flat_map(move |event| {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link { .. } | Tag::Image { .. }) => depth += 1,
+            Event::End(TagEnd::CodeBlock | TagEnd::Link | TagEnd::Image) => {
+                depth = depth.saturating_sub(1);
+            },
+            Event::Text(ref text) if depth == 0 => return split_hashtags(text),
+            _ => {},
+        }
+
+        vec![Fragment::Event(event)]
+    })
+}
+
+fn split_hashtags(text: &str) -> Vec<Fragment<'static>> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let plain = |s: &str| Fragment::Event(pulldown_cmark::Event::Text(s.to_owned().into()));
+
+    let (mut fragments, mut rest) = (vec![], 0);
+    let mut prev = None;
+
+    for (i, c) in text.char_indices() {
+        // inside of the previous hashtag
+        if i < rest {
+            continue;
+        }
+
+        let is_start = c == '#' && !prev.is_some_and(|p| is_word(p) || p == '#');
+        prev = Some(c);
+
+        if !is_start {
+            continue;
+        }
+
+        let body = &text[i + 1..];
+        let body = &body[..body.find(|c: char| !is_word(c)).unwrap_or(body.len())];
+
+        // e.g. `#1` isn't a hashtag
+        if body.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        if rest < i {
+            fragments.push(plain(&text[rest..i]));
         }
+
+        rest = i + 1 + body.len();
+        prev = body.chars().last();
+
+        fragments.push(Fragment::Hashtag {
+            text: text[i..rest].to_owned(),
+            tag: body.to_lowercase(),
+        });
     }
+
+    if rest < text.len() {
+        fragments.push(plain(&text[rest..]));
+    }
+
+    fragments
+}
+
+/// position in the timeline, ordered by `(created_at, id)` descending
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cursor {
+    pub created_at: DateTime,
+    pub id: i64,
+}
+
+/// hashtag with the number of (not deleted) posts
+pub struct Tag {
+    pub name: String,
+    pub count: i64,
+}
+
+/// query of full-text search, as `to_tsquery` accepts
+///
+/// - `word` matches the word, `word*` matches words starting with it
+/// - `"some words"` matches the phrase
+/// - `-word` / `-"some words"` excludes posts matching it
+/// - `before:YYYY-MM-DD` / `after:YYYY-MM-DD` filters posts by the date (UTC) posted
+#[derive(PartialEq, Eq, Debug)]
+pub struct SearchQuery {
+    pub tsquery: String,
+    pub before: Option<DateTime>,
+    pub after: Option<DateTime>,
+}
+
+impl SearchQuery {
+    /// `None` if it's malformed or has no term to match
+    pub fn parse(q: &str) -> Option<Self> {
+        let (mut terms, mut has_positive) = (vec![], false);
+        let (mut before, mut after) = (None, None);
+
+        let mut chars = q.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let Some(&c) = chars.peek() else { break };
+
+            let negated = c == '-' && chars.next().is_some();
+
+            let term = if chars.next_if_eq(&'"').is_some() {
+                let phrase = chars.by_ref().take_while(|c| *c != '"').collect::<String>();
+                let words = phrase.split_whitespace().map(quote).collect::<Vec<_>>();
+
+                if words.is_empty() {
+                    continue;
+                }
+
+                format!("({})", words.join(" <-> "))
+            } else {
+                let word = chars.by_ref().take_while(|c| !c.is_whitespace()).collect::<String>();
+
+                let date = |d: &str| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+
+                match (negated, word.split_once(':')) {
+                    (false, Some(("before", d))) => {
+                        before = Some(date(d)?.and_time(chrono::NaiveTime::MIN));
+                        continue;
+                    },
+                    (false, Some(("after", d))) => {
+                        after = Some(date(d)?.succ_opt()?.and_time(chrono::NaiveTime::MIN));
+                        continue;
+                    },
+                    _ => {},
+                }
+
+                match word.trim_end_matches('*') {
+                    "" => continue,
+                    w if w.len() < word.len() => format!("{}:*", quote(w)),
+                    w => quote(w),
+                }
+            };
+
+            has_positive |= !negated;
+            terms.push(if negated { format!("!{term}") } else { term });
+        }
+
+        if !has_positive {
+            return None;
+        }
+
+        Some(Self {
+            tsquery: terms.join(" & "),
+            before,
+            after,
+        })
+    }
+}
+
+/// quotes as a lexeme of `to_tsquery`, which is still normalized by the configuration
+fn quote(word: &str) -> String {
+    let escaped = word.replace('\\', "\\\\").replace('\'', "''");
+
+    format!("'{escaped}'")
+}
+
+/// post matched by full-text search
+pub struct SearchHit {
+    pub post: Post,
+    pub rank: f32,
+    /// fragments of `content`, matches are surrounded by `HIGHLIGHT`
+    pub headline: String,
+}
+
+impl SearchHit {
+    /// sentinels around matches in `headline`, removed from `content` beforehand
+    pub const HIGHLIGHT: (char, char) = ('\u{2}', '\u{3}');
+}
+
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub handle: webauthn_rs::prelude::Uuid,
+    pub created_at: DateTime,
+}
+
+pub struct Key {
+    pub id: u32,
+    pub user_id: i64,
+    pub passkey: webauthn_rs::prelude::Passkey,
+    pub counter: u32,
+    pub nickname: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+pub struct Invitation {
+    pub code: String,
+    pub issued_by: i64,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+    pub used_at: Option<DateTime>,
+}
+
+impl Invitation {
+    pub fn is_available(&self, now: DateTime) -> bool { self.used_at.is_none() && now < self.expires_at }
+}
+
+#[test]
+fn parse_search_query() {
+    let date = |m, d| chrono::NaiveDate::from_ymd_opt(2024, m, d)?.and_hms_opt(0, 0, 0);
+
+    let q = r#"rust "full text" -java pg* it's before:2024-03-01 after:2024-01-31"#;
+
+    assert_eq!(
+        SearchQuery::parse(q),
+        Some(SearchQuery {
+            tsquery: "'rust' & ('full' <-> 'text') & !'java' & 'pg':* & 'it''s'".into(),
+            before: date(3, 1),
+            after: date(2, 1),
+        })
+    );
+
+    assert_eq!(SearchQuery::parse("-java before:2024-03-01"), None);
+    assert_eq!(SearchQuery::parse("rust before:yesterday"), None);
+    assert_eq!(SearchQuery::parse(r#""" *"#), None);
+}
+
+#[test]
+fn post_tags() {
+    let content = concat!(
+        "#Rust and #rust_lang, not#this or #1\n\n",
+        "```\n#code\n```\n\n",
+        "[#link](/) `#code` #日本語",
+    );
+
+    let post = Post {
+        id: 1,
+        author_id: 1,
+        content: content.to_owned(),
+        posted_at: DateTime::default(),
+        created_at: DateTime::default(),
+        is_deleted: false,
+    };
+
+    assert_eq!(post.tags(), ["rust", "rust_lang", "日本語"]);
 }
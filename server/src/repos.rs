@@ -5,22 +5,57 @@ use crate::utils::IteratorExt;
 use crate::{models, rows};
 
 mod ext {
-    pub use webauthn_rs::prelude::Passkey;
+    pub use webauthn_rs::prelude::Uuid;
 }
 
 pub trait PostRepository {
     async fn all(&self) -> anyhow::Result<Vec<models::Post>>;
-    async fn find_one(&self, id: u32) -> anyhow::Result<Option<models::Post>>;
-    async fn find_all(&self, id: u32) -> anyhow::Result<Vec<models::Post>>;
-    async fn create(&self, model: models::Post) -> anyhow::Result<()>;
+    async fn page(
+        &self,
+        cursor: Option<models::Cursor>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::Post>>;
+    async fn find_one(&self, id: i64) -> anyhow::Result<Option<models::Post>>;
+    async fn find_all(&self, id: i64) -> anyhow::Result<Vec<models::Post>>;
+    async fn find_revision(
+        &self,
+        id: i64,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<Option<models::Post>>;
+    async fn create(
+        &self,
+        author_id: i64,
+        content: String,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<i64>;
     async fn update(
         &self,
-        id: u32,
+        id: i64,
         content: String,
         created_at: models::DateTime,
     ) -> anyhow::Result<()>;
-    async fn delete(&self, id: u32) -> anyhow::Result<()>;
-    async fn restore(&self, id: u32) -> anyhow::Result<()>;
+    async fn revert(
+        &self,
+        id: i64,
+        revision: models::DateTime,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<()>;
+    async fn delete(&self, id: i64) -> anyhow::Result<()>;
+    async fn restore(&self, id: i64) -> anyhow::Result<()>;
+    async fn search(
+        &self,
+        query: &models::SearchQuery,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::SearchHit>>;
+    /// replaces hashtags of the post, with ones of the latest revision
+    async fn set_tags(&self, id: i64, tags: &[String]) -> anyhow::Result<()>;
+    async fn tags(&self) -> anyhow::Result<Vec<models::Tag>>;
+    async fn page_by_tag(
+        &self,
+        tag: &str,
+        cursor: Option<models::Cursor>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::Post>>;
 }
 
 pub struct PgRepository(sqlx::PgPool);
@@ -30,7 +65,72 @@ impl PgRepository {
         let db = sqlx::PgPool::connect(p.as_ref()).await?;
         sqlx::migrate!().run(&db).await?;
 
-        Ok(Self(db))
+        let repo = Self(db);
+        repo.fill_cred_ids().await?;
+        repo.fill_tags().await?;
+
+        Ok(repo)
+    }
+
+    /// fills `keys.cred_id` of rows inserted before the column exists
+    async fn fill_cred_ids(&self) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY_0: &str = "SELECT * FROM keys WHERE cred_id IS NULL";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "UPDATE keys SET cred_id = $1 WHERE id = $2";
+
+        let mut tx = self.0.begin().await?;
+
+        let models = sqlx::query(QUERY_0)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(rows::Key::from_row)
+            .try_map(rows::Key::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        for model in models {
+            let rows::Key { id, cred_id, .. } = rows::Key::from_model(model)?;
+
+            sqlx::query(QUERY_1)
+                .bind(cred_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// syncs `post_tags` of posts whose latest revision isn't tagged, e.g. created before it exists
+    async fn fill_tags(&self) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_flags AS pf \
+                             JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                             WHERE pf.tagged_at IS DISTINCT FROM pf.latest_at";
+
+        let tx = self.begin().await?;
+
+        let models = sqlx::query(QUERY)
+            .fetch_all(&mut *tx.connect().await?)
+            .await?
+            .iter()
+            .map(rows::Post::from_row)
+            .try_map(rows::Post::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        for model in models {
+            tx.set_tags(model.id, &model.tags()).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
     }
 }
 
@@ -40,19 +140,78 @@ impl core::ops::Deref for PgRepository {
     fn deref(&self) -> &Self::Target { &self.0 }
 }
 
-impl PostRepository for PgRepository {
+/// repository which can begin a unit of work, all operations through it are committed at once
+pub trait Transactional {
+    type Transaction: Transaction
+        + PostRepository
+        + KeyRepository
+        + UserRepository
+        + InvitationRepository;
+
+    async fn begin(&self) -> anyhow::Result<Self::Transaction>;
+}
+
+/// unit of work, dropping without [`Transaction::commit`] rolls back
+pub trait Transaction {
+    async fn commit(self) -> anyhow::Result<()>;
+}
+
+/// source of connection to postgres, shared by pool and transaction
+pub trait Connect {
+    type Connection<'a>: core::ops::DerefMut<Target = sqlx::PgConnection>
+    where Self: 'a;
+
+    async fn connect(&self) -> anyhow::Result<Self::Connection<'_>>;
+}
+
+impl Connect for PgRepository {
+    type Connection<'a> = sqlx::pool::PoolConnection<sqlx::Postgres>;
+
+    async fn connect(&self) -> anyhow::Result<Self::Connection<'_>> { Ok(self.0.acquire().await?) }
+}
+
+impl Transactional for PgRepository {
+    type Transaction = PgTransaction;
+
+    async fn begin(&self) -> anyhow::Result<Self::Transaction> {
+        let tx = self.0.begin().await?;
+
+        Ok(PgTransaction(tokio::sync::Mutex::new(tx)))
+    }
+}
+
+pub struct PgTransaction(tokio::sync::Mutex<sqlx::Transaction<'static, sqlx::Postgres>>);
+
+impl Connect for PgTransaction {
+    type Connection<'a> = tokio::sync::MappedMutexGuard<'a, sqlx::PgConnection>;
+
+    async fn connect(&self) -> anyhow::Result<Self::Connection<'_>> {
+        let guard = self.0.lock().await;
+
+        Ok(tokio::sync::MutexGuard::map(guard, |tx| &mut **tx))
+    }
+}
+
+impl Transaction for PgTransaction {
+    async fn commit(self) -> anyhow::Result<()> {
+        self.0.into_inner().commit().await?;
+
+        Ok(())
+    }
+}
+
+impl<C: Connect> PostRepository for C {
     async fn all(&self) -> anyhow::Result<Vec<models::Post>> {
         #[rustfmt::skip]
-        const QUERY: &str = "SELECT p.*, pf.is_deleted FROM posts AS p \
-                             JOIN post_flags AS pf ON p.id = pf.id \
-                             WHERE p.created_at = ( \
-                                 SELECT MAX(created_at) FROM posts \
-                                 WHERE id = p.id \
-                             ) AND pf.is_deleted = FALSE \
-                             ORDER BY created_at DESC, p.id DESC";
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_flags AS pf \
+                             JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                             WHERE pf.is_deleted = FALSE \
+                             ORDER BY pf.latest_at DESC, pf.id DESC";
+
+        let mut conn = self.connect().await?;
 
         let models = sqlx::query(QUERY)
-            .fetch_all(&**self)
+            .fetch_all(&mut *conn)
             .await?
             .iter()
             .map(rows::Post::from_row)
@@ -63,15 +222,60 @@ impl PostRepository for PgRepository {
         Ok(models)
     }
 
-    async fn find_one(&self, id: u32) -> anyhow::Result<Option<models::Post>> {
+    async fn page(
+        &self,
+        cursor: Option<models::Cursor>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::Post>> {
+        // both are served by index `post_flags_timeline`
+        #[rustfmt::skip]
+        const QUERY_HEAD: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_flags AS pf \
+                                  JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                                  WHERE pf.is_deleted = FALSE \
+                                  ORDER BY pf.latest_at DESC, pf.id DESC \
+                                  LIMIT $1";
+
         #[rustfmt::skip]
-        const QUERY: &str = "SELECT p.*, pf.is_deleted FROM posts AS p \
+        const QUERY_NEXT: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_flags AS pf \
+                                  JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                                  WHERE pf.is_deleted = FALSE \
+                                  AND (pf.latest_at, pf.id) < ($2, $3) \
+                                  ORDER BY pf.latest_at DESC, pf.id DESC \
+                                  LIMIT $1";
+
+        let query = match cursor {
+            None => sqlx::query(QUERY_HEAD).bind(i64::from(limit)),
+            Some(models::Cursor { created_at, id }) => sqlx::query(QUERY_NEXT)
+                .bind(i64::from(limit))
+                .bind(created_at)
+                .bind(id),
+        };
+
+        let mut conn = self.connect().await?;
+
+        let models = query
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(rows::Post::from_row)
+            .try_map(rows::Post::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        Ok(models)
+    }
+
+    async fn find_one(&self, id: i64) -> anyhow::Result<Option<models::Post>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM posts AS p \
                              JOIN post_flags AS pf ON p.id = pf.id \
                              WHERE p.id = $1 \
                              ORDER BY p.created_at DESC \
                              LIMIT 1";
 
-        let model = match sqlx::query(QUERY).bind(id as i64).fetch_one(&**self).await {
+        let mut conn = self.connect().await?;
+
+        let model = match sqlx::query(QUERY).bind(id).fetch_one(&mut *conn).await {
             Ok(ref row) => rows::Post::from_row(row)?.into_model()?,
             Err(sqlx::Error::RowNotFound) => return Ok(None),
             Err(err) => anyhow::bail!(err),
@@ -80,16 +284,18 @@ impl PostRepository for PgRepository {
         Ok(Some(model))
     }
 
-    async fn find_all(&self, id: u32) -> anyhow::Result<Vec<models::Post>> {
+    async fn find_all(&self, id: i64) -> anyhow::Result<Vec<models::Post>> {
         #[rustfmt::skip]
-        const QUERY: &str = "SELECT p.*, pf.is_deleted FROM posts AS p \
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM posts AS p \
                              JOIN post_flags AS pf ON p.id = pf.id \
                              WHERE p.id = $1 \
                              ORDER BY p.created_at DESC";
 
+        let mut conn = self.connect().await?;
+
         let models = sqlx::query(QUERY)
-            .bind(id as i64)
-            .fetch_all(&**self)
+            .bind(id)
+            .fetch_all(&mut *conn)
             .await?
             .iter()
             .map(rows::Post::from_row)
@@ -100,64 +306,127 @@ impl PostRepository for PgRepository {
         Ok(models)
     }
 
-    async fn create(&self, model: models::Post) -> anyhow::Result<()> {
+    async fn find_revision(
+        &self,
+        id: i64,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<Option<models::Post>> {
         #[rustfmt::skip]
-        const QUERY_0: &str = "INSERT INTO posts (id, content, posted_at, created_at) \
-                               VALUES ($1, $2, $3, $4)";
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM posts AS p \
+                             JOIN post_flags AS pf ON p.id = pf.id \
+                             WHERE p.id = $1 AND p.created_at = $2";
+
+        let query = sqlx::query(QUERY).bind(id).bind(created_at);
 
+        let mut conn = self.connect().await?;
+
+        let model = match query.fetch_one(&mut *conn).await {
+            Ok(ref row) => rows::Post::from_row(row)?.into_model()?,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => anyhow::bail!(err),
+        };
+
+        Ok(Some(model))
+    }
+
+    async fn create(
+        &self,
+        author_id: i64,
+        content: String,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<i64> {
         #[rustfmt::skip]
-        const QUERY_1: &str = "INSERT INTO post_flags (id, is_deleted) \
-                               VALUES ($1, $2)";
+        const QUERY_0: &str = "INSERT INTO post_flags (is_deleted, latest_at, author_id) \
+                               VALUES (FALSE, $1, $2) \
+                               RETURNING id";
 
-        let rows::Post {
-            id,
-            content,
-            posted_at,
-            created_at,
-            is_deleted,
-        } = rows::Post::from_model(model)?;
+        #[rustfmt::skip]
+        const QUERY_1: &str = "INSERT INTO posts (id, content, posted_at, created_at) \
+                               VALUES ($1, $2, $3, $3)";
 
-        let result = sqlx::query(QUERY_0)
+        let mut conn = self.connect().await?;
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+
+        let id = sqlx::query_scalar::<_, i64>(QUERY_0)
+            .bind(created_at)
+            .bind(author_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let result = sqlx::query(QUERY_1)
             .bind(id)
             .bind(content)
-            .bind(posted_at)
             .bind(created_at)
-            .execute(&**self)
+            .execute(&mut *tx)
             .await?;
 
         if result.rows_affected() != 1 {
             anyhow::bail!("failed to insert into posts");
         }
 
-        let result = sqlx::query(QUERY_1)
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn update(
+        &self,
+        id: i64,
+        content: String,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "WITH p AS ( \
+                                 INSERT INTO posts (id, content, posted_at, created_at) \
+                                 SELECT p.id, $1, p.posted_at, $2 FROM posts AS p \
+                                 JOIN post_flags AS pf ON p.id = pf.id AND p.created_at = pf.latest_at \
+                                 WHERE p.id = $3 \
+                                 RETURNING id, created_at \
+                             ) \
+                             UPDATE post_flags AS pf \
+                             SET latest_at = p.created_at FROM p \
+                             WHERE pf.id = p.id";
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY)
+            .bind(content)
+            .bind(created_at)
             .bind(id)
-            .bind(is_deleted)
-            .execute(&**self)
+            .execute(&mut *conn)
             .await?;
 
         if result.rows_affected() != 1 {
-            anyhow::bail!("failed to insert into post_flags");
+            anyhow::bail!("failed to insert into posts");
         }
 
         Ok(())
     }
 
-    async fn update(
+    async fn revert(
         &self,
-        id: u32,
-        content: String,
+        id: i64,
+        revision: models::DateTime,
         created_at: models::DateTime,
     ) -> anyhow::Result<()> {
         #[rustfmt::skip]
-        const QUERY: &str = "INSERT INTO posts (id, content, posted_at, created_at) \
-                             SELECT id, $1, posted_at, $2 FROM posts \
-                             WHERE id = $3";
+        const QUERY: &str = "WITH p AS ( \
+                                 INSERT INTO posts (id, content, posted_at, created_at) \
+                                 SELECT id, content, posted_at, $3 FROM posts \
+                                 WHERE id = $1 AND created_at = $2 \
+                                 RETURNING id, created_at \
+                             ) \
+                             UPDATE post_flags AS pf \
+                             SET latest_at = p.created_at FROM p \
+                             WHERE pf.id = p.id";
+
+        let mut conn = self.connect().await?;
 
         let result = sqlx::query(QUERY)
-            .bind(content)
+            .bind(id)
+            .bind(revision)
             .bind(created_at)
-            .bind(id as i64)
-            .execute(&**self)
+            .execute(&mut *conn)
             .await?;
 
         if result.rows_affected() != 1 {
@@ -167,13 +436,15 @@ impl PostRepository for PgRepository {
         Ok(())
     }
 
-    async fn delete(&self, id: u32) -> anyhow::Result<()> {
+    async fn delete(&self, id: i64) -> anyhow::Result<()> {
         #[rustfmt::skip]
         const QUERY: &str = "UPDATE post_flags \
                              SET is_deleted = TRUE \
                              WHERE id = $1";
 
-        let result = sqlx::query(QUERY).bind(id as i64).execute(&**self).await?;
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY).bind(id).execute(&mut *conn).await?;
 
         if result.rows_affected() != 1 {
             anyhow::bail!("failed to update post_flags");
@@ -182,13 +453,15 @@ impl PostRepository for PgRepository {
         Ok(())
     }
 
-    async fn restore(&self, id: u32) -> anyhow::Result<()> {
+    async fn restore(&self, id: i64) -> anyhow::Result<()> {
         #[rustfmt::skip]
         const QUERY: &str = "UPDATE post_flags \
                              SET is_deleted = FALSE \
                              WHERE id = $1";
 
-        let result = sqlx::query(QUERY).bind(id as i64).execute(&**self).await?;
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY).bind(id).execute(&mut *conn).await?;
 
         if result.rows_affected() != 1 {
             anyhow::bail!("failed to update post_flags");
@@ -196,22 +469,170 @@ impl PostRepository for PgRepository {
 
         Ok(())
     }
+
+    async fn search(
+        &self,
+        query: &models::SearchQuery,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::SearchHit>> {
+        // matched by index `posts_search`, then narrowed to latest revisions
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT p.*, pf.is_deleted, pf.author_id, \
+                                    ts_rank(p.search, q) AS rank, \
+                                    ts_headline('simple', translate(p.content, $5, ''), q, $6) AS headline \
+                             FROM post_flags AS pf \
+                             JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                             CROSS JOIN to_tsquery('simple', $1) AS q \
+                             WHERE pf.is_deleted = FALSE AND p.search @@ q \
+                             AND ($2::TIMESTAMP IS NULL OR p.posted_at < $2) \
+                             AND ($3::TIMESTAMP IS NULL OR p.posted_at >= $3) \
+                             ORDER BY rank DESC, pf.latest_at DESC, pf.id DESC \
+                             LIMIT $4";
+
+        let (start, stop) = models::SearchHit::HIGHLIGHT;
+        let options = format!("StartSel={start}, StopSel={stop}, MaxFragments=3, MaxWords=20");
+
+        let mut conn = self.connect().await?;
+
+        let models = sqlx::query(QUERY)
+            .bind(&query.tsquery)
+            .bind(query.before)
+            .bind(query.after)
+            .bind(i64::from(limit))
+            .bind(format!("{start}{stop}"))
+            .bind(options)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(rows::SearchHit::from_row)
+            .try_map(rows::SearchHit::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        Ok(models)
+    }
+
+    async fn set_tags(&self, id: i64, tags: &[String]) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY_0: &str = "DELETE FROM post_tags \
+                               WHERE id = $1 AND NOT (tag = ANY($2))";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "INSERT INTO post_tags (id, tag) \
+                               SELECT $1, UNNEST($2::TEXT[]) \
+                               ON CONFLICT DO NOTHING";
+
+        #[rustfmt::skip]
+        const QUERY_2: &str = "UPDATE post_flags \
+                               SET tagged_at = latest_at \
+                               WHERE id = $1";
+
+        let mut conn = self.connect().await?;
+
+        sqlx::query(QUERY_0).bind(id).bind(tags).execute(&mut *conn).await?;
+        sqlx::query(QUERY_1).bind(id).bind(tags).execute(&mut *conn).await?;
+
+        let result = sqlx::query(QUERY_2).bind(id).execute(&mut *conn).await?;
+
+        if result.rows_affected() != 1 {
+            anyhow::bail!("failed to update post_flags");
+        }
+
+        Ok(())
+    }
+
+    async fn tags(&self) -> anyhow::Result<Vec<models::Tag>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT pt.tag AS name, COUNT(*) AS count FROM post_tags AS pt \
+                             JOIN post_flags AS pf ON pf.id = pt.id \
+                             WHERE pf.is_deleted = FALSE \
+                             GROUP BY pt.tag \
+                             ORDER BY count DESC, name";
+
+        let mut conn = self.connect().await?;
+
+        let models = sqlx::query(QUERY)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(rows::Tag::from_row)
+            .try_map(rows::Tag::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        Ok(models)
+    }
+
+    async fn page_by_tag(
+        &self,
+        tag: &str,
+        cursor: Option<models::Cursor>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<models::Post>> {
+        #[rustfmt::skip]
+        const QUERY_HEAD: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_tags AS pt \
+                                  JOIN post_flags AS pf ON pf.id = pt.id \
+                                  JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                                  WHERE pt.tag = $2 AND pf.is_deleted = FALSE \
+                                  ORDER BY pf.latest_at DESC, pf.id DESC \
+                                  LIMIT $1";
+
+        #[rustfmt::skip]
+        const QUERY_NEXT: &str = "SELECT p.*, pf.is_deleted, pf.author_id FROM post_tags AS pt \
+                                  JOIN post_flags AS pf ON pf.id = pt.id \
+                                  JOIN posts AS p ON p.id = pf.id AND p.created_at = pf.latest_at \
+                                  WHERE pt.tag = $2 AND pf.is_deleted = FALSE \
+                                  AND (pf.latest_at, pf.id) < ($3, $4) \
+                                  ORDER BY pf.latest_at DESC, pf.id DESC \
+                                  LIMIT $1";
+
+        let query = match cursor {
+            None => sqlx::query(QUERY_HEAD).bind(i64::from(limit)).bind(tag),
+            Some(models::Cursor { created_at, id }) => sqlx::query(QUERY_NEXT)
+                .bind(i64::from(limit))
+                .bind(tag)
+                .bind(created_at)
+                .bind(id),
+        };
+
+        let mut conn = self.connect().await?;
+
+        let models = query
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(rows::Post::from_row)
+            .try_map(rows::Post::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        Ok(models)
+    }
 }
 
 pub trait KeyRepository {
-    async fn all(&self) -> anyhow::Result<Vec<ext::Passkey>>;
-    async fn get(&self, id: u32) -> anyhow::Result<Option<ext::Passkey>>;
-    async fn push(&self, id: u32, model: ext::Passkey) -> anyhow::Result<()>;
+    async fn all_keys(&self) -> anyhow::Result<Vec<models::Key>>;
+    async fn find_by_user(&self, user_id: i64) -> anyhow::Result<Vec<models::Key>>;
+    async fn find_by_cred(&self, cred_id: &[u8]) -> anyhow::Result<Option<models::Key>>;
+    async fn get(&self, id: u32) -> anyhow::Result<Option<models::Key>>;
+    async fn push(&self, model: models::Key) -> anyhow::Result<()>;
+    async fn rename(&self, id: u32, nickname: Option<String>) -> anyhow::Result<()>;
+    /// stores credential state and usage of the key
+    async fn update_key(&self, model: models::Key) -> anyhow::Result<()>;
     async fn remove(&self, id: u32) -> anyhow::Result<()>;
+    /// blocks adding keys by others until the end of transaction, so it must be in one
+    async fn lock_keys(&self) -> anyhow::Result<()>;
 }
 
-impl KeyRepository for PgRepository {
-    async fn all(&self) -> anyhow::Result<Vec<ext::Passkey>> {
+impl<C: Connect> KeyRepository for C {
+    async fn all_keys(&self) -> anyhow::Result<Vec<models::Key>> {
         #[rustfmt::skip]
-        const QUERY: &str = "SELECT content FROM keys";
+        const QUERY: &str = "SELECT * FROM keys";
+
+        let mut conn = self.connect().await?;
 
         let models = sqlx::query(QUERY)
-            .fetch_all(&**self)
+            .fetch_all(&mut *conn)
             .await?
             .iter()
             .map(rows::Key::from_row)
@@ -222,11 +643,32 @@ impl KeyRepository for PgRepository {
         Ok(models)
     }
 
-    async fn get(&self, id: u32) -> anyhow::Result<Option<ext::Passkey>> {
+    async fn find_by_user(&self, user_id: i64) -> anyhow::Result<Vec<models::Key>> {
         #[rustfmt::skip]
-        const QUERY: &str = "SELECT content FROM keys WHERE id = $1";
+        const QUERY: &str = "SELECT * FROM keys WHERE user_id = $1";
+
+        let mut conn = self.connect().await?;
 
-        let model = match sqlx::query(QUERY).bind(id as i64).fetch_one(&**self).await {
+        let models = sqlx::query(QUERY)
+            .bind(user_id)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(rows::Key::from_row)
+            .try_map(rows::Key::into_model)
+            .map(|r| r.map_err(anyhow::Error::new).flatten())
+            .try_collect::<Vec<_>>()?;
+
+        Ok(models)
+    }
+
+    async fn find_by_cred(&self, cred_id: &[u8]) -> anyhow::Result<Option<models::Key>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT * FROM keys WHERE cred_id = $1";
+
+        let mut conn = self.connect().await?;
+
+        let model = match sqlx::query(QUERY).bind(cred_id).fetch_one(&mut *conn).await {
             Ok(ref row) => rows::Key::from_row(row)?.into_model()?,
             Err(sqlx::Error::RowNotFound) => return Ok(None),
             Err(err) => anyhow::bail!(err),
@@ -235,17 +677,49 @@ impl KeyRepository for PgRepository {
         Ok(Some(model))
     }
 
-    async fn push(&self, id: u32, model: ext::Passkey) -> anyhow::Result<()> {
+    async fn get(&self, id: u32) -> anyhow::Result<Option<models::Key>> {
         #[rustfmt::skip]
-        const QUERY: &str = "INSERT INTO keys (id, content) \
-                             VALUES ($1, $2)";
+        const QUERY: &str = "SELECT * FROM keys WHERE id = $1";
+
+        let mut conn = self.connect().await?;
 
-        let rows::Key { content } = rows::Key::from_model(model)?;
+        let model = match sqlx::query(QUERY).bind(i64::from(id)).fetch_one(&mut *conn).await {
+            Ok(ref row) => rows::Key::from_row(row)?.into_model()?,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => anyhow::bail!(err),
+        };
+
+        Ok(Some(model))
+    }
+
+    async fn push(&self, model: models::Key) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "INSERT INTO keys (id, user_id, content, counter, nickname, created_at, last_used_at, cred_id) \
+                             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)";
+
+        let rows::Key {
+            id,
+            user_id,
+            content,
+            counter,
+            nickname,
+            created_at,
+            last_used_at,
+            cred_id,
+        } = rows::Key::from_model(model)?;
+
+        let mut conn = self.connect().await?;
 
         let result = sqlx::query(QUERY)
-            .bind(id as i64)
+            .bind(id)
+            .bind(user_id)
             .bind(content)
-            .execute(&**self)
+            .bind(counter)
+            .bind(nickname)
+            .bind(created_at)
+            .bind(last_used_at)
+            .bind(cred_id)
+            .execute(&mut *conn)
             .await?;
 
         if result.rows_affected() != 1 {
@@ -255,11 +729,65 @@ impl KeyRepository for PgRepository {
         Ok(())
     }
 
+    async fn rename(&self, id: u32, nickname: Option<String>) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "UPDATE keys \
+                             SET nickname = $1 \
+                             WHERE id = $2";
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY)
+            .bind(nickname)
+            .bind(i64::from(id))
+            .execute(&mut *conn)
+            .await?;
+
+        if result.rows_affected() != 1 {
+            anyhow::bail!("failed to update keys");
+        }
+
+        Ok(())
+    }
+
+    async fn update_key(&self, model: models::Key) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "UPDATE keys \
+                             SET content = $1, counter = $2, last_used_at = $3 \
+                             WHERE id = $4";
+
+        let rows::Key {
+            id,
+            content,
+            counter,
+            last_used_at,
+            ..
+        } = rows::Key::from_model(model)?;
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY)
+            .bind(content)
+            .bind(counter)
+            .bind(last_used_at)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        if result.rows_affected() != 1 {
+            anyhow::bail!("failed to update keys");
+        }
+
+        Ok(())
+    }
+
     async fn remove(&self, id: u32) -> anyhow::Result<()> {
         #[rustfmt::skip]
         const QUERY: &str = "DELETE FROM keys WHERE id = $1";
 
-        let result = sqlx::query(QUERY).bind(id as i64).execute(&**self).await?;
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY).bind(i64::from(id)).execute(&mut *conn).await?;
 
         if result.rows_affected() != 1 {
             anyhow::bail!("failed to delete from keys");
@@ -267,4 +795,332 @@ impl KeyRepository for PgRepository {
 
         Ok(())
     }
+
+    async fn lock_keys(&self) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "LOCK TABLE keys IN SHARE ROW EXCLUSIVE MODE";
+
+        let mut conn = self.connect().await?;
+
+        sqlx::query(QUERY).execute(&mut *conn).await?;
+
+        Ok(())
+    }
+}
+
+pub trait UserRepository {
+    async fn find_user(&self, id: i64) -> anyhow::Result<Option<models::User>>;
+    async fn find_user_by_name(&self, name: &str) -> anyhow::Result<Option<models::User>>;
+    async fn create_user(
+        &self,
+        name: String,
+        handle: ext::Uuid,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<i64>;
+}
+
+impl<C: Connect> UserRepository for C {
+    async fn find_user(&self, id: i64) -> anyhow::Result<Option<models::User>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT * FROM users WHERE id = $1";
+
+        let mut conn = self.connect().await?;
+
+        let model = match sqlx::query(QUERY).bind(id).fetch_one(&mut *conn).await {
+            Ok(ref row) => rows::User::from_row(row)?.into_model()?,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => anyhow::bail!(err),
+        };
+
+        Ok(Some(model))
+    }
+
+    async fn find_user_by_name(&self, name: &str) -> anyhow::Result<Option<models::User>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT * FROM users WHERE name = $1";
+
+        let mut conn = self.connect().await?;
+
+        let model = match sqlx::query(QUERY).bind(name).fetch_one(&mut *conn).await {
+            Ok(ref row) => rows::User::from_row(row)?.into_model()?,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => anyhow::bail!(err),
+        };
+
+        Ok(Some(model))
+    }
+
+    async fn create_user(
+        &self,
+        name: String,
+        handle: ext::Uuid,
+        created_at: models::DateTime,
+    ) -> anyhow::Result<i64> {
+        #[rustfmt::skip]
+        const QUERY: &str = "INSERT INTO users (name, handle, created_at) \
+                             VALUES ($1, $2, $3) \
+                             RETURNING id";
+
+        let mut conn = self.connect().await?;
+
+        let id = sqlx::query_scalar::<_, i64>(QUERY)
+            .bind(name)
+            .bind(handle.as_bytes().as_slice())
+            .bind(created_at)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        Ok(id)
+    }
+}
+
+pub trait InvitationRepository {
+    async fn find_invitation(&self, code: &str) -> anyhow::Result<Option<models::Invitation>>;
+    async fn issue_invitation(&self, model: models::Invitation) -> anyhow::Result<()>;
+    /// marks the invitation as used, returns `false` if it isn't available
+    async fn consume_invitation(&self, code: &str, now: models::DateTime) -> anyhow::Result<bool>;
+}
+
+impl<C: Connect> InvitationRepository for C {
+    async fn find_invitation(&self, code: &str) -> anyhow::Result<Option<models::Invitation>> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT * FROM invitations WHERE code = $1";
+
+        let mut conn = self.connect().await?;
+
+        let model = match sqlx::query(QUERY).bind(code).fetch_one(&mut *conn).await {
+            Ok(ref row) => rows::Invitation::from_row(row)?.into_model()?,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => anyhow::bail!(err),
+        };
+
+        Ok(Some(model))
+    }
+
+    async fn issue_invitation(&self, model: models::Invitation) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "INSERT INTO invitations (code, issued_by, created_at, expires_at, used_at) \
+                             VALUES ($1, $2, $3, $4, $5)";
+
+        let rows::Invitation {
+            code,
+            issued_by,
+            created_at,
+            expires_at,
+            used_at,
+        } = rows::Invitation::from_model(model)?;
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY)
+            .bind(code)
+            .bind(issued_by)
+            .bind(created_at)
+            .bind(expires_at)
+            .bind(used_at)
+            .execute(&mut *conn)
+            .await?;
+
+        if result.rows_affected() != 1 {
+            anyhow::bail!("failed to insert into invitations");
+        }
+
+        Ok(())
+    }
+
+    async fn consume_invitation(&self, code: &str, now: models::DateTime) -> anyhow::Result<bool> {
+        #[rustfmt::skip]
+        const QUERY: &str = "UPDATE invitations \
+                             SET used_at = $2 \
+                             WHERE code = $1 AND used_at IS NULL AND expires_at > $2";
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY).bind(code).bind(now).execute(&mut *conn).await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+pub trait TokenRepository {
+    /// whether the token is revoked, by itself, by revoking all tokens of the user or by removing
+    /// the key which the user is authenticated with
+    async fn is_revoked(
+        &self,
+        jti: &str,
+        user_id: i64,
+        key_id: u32,
+        issued_at: models::DateTime,
+    ) -> anyhow::Result<bool>;
+    async fn revoke(&self, jti: &str, expires_at: models::DateTime) -> anyhow::Result<()>;
+    async fn revoke_all(&self, user_id: i64, now: models::DateTime) -> anyhow::Result<()>;
+}
+
+impl<C: Connect> TokenRepository for C {
+    async fn is_revoked(
+        &self,
+        jti: &str,
+        user_id: i64,
+        key_id: u32,
+        issued_at: models::DateTime,
+    ) -> anyhow::Result<bool> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1) \
+                             OR EXISTS (SELECT 1 FROM users WHERE id = $2 AND tokens_revoked_at >= $4) \
+                             OR NOT EXISTS (SELECT 1 FROM keys WHERE id = $3 AND user_id = $2)";
+
+        let mut conn = self.connect().await?;
+
+        let is_revoked = sqlx::query_scalar::<_, bool>(QUERY)
+            .bind(jti)
+            .bind(user_id)
+            .bind(i64::from(key_id))
+            .bind(issued_at)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        Ok(is_revoked)
+    }
+
+    async fn revoke(&self, jti: &str, expires_at: models::DateTime) -> anyhow::Result<()> {
+        // expired ones are rejected without the list
+        #[rustfmt::skip]
+        const QUERY_0: &str = "DELETE FROM revoked_tokens \
+                               WHERE expires_at < NOW() AT TIME ZONE 'UTC'";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "INSERT INTO revoked_tokens (jti, expires_at) \
+                               VALUES ($1, $2) \
+                               ON CONFLICT DO NOTHING";
+
+        let mut conn = self.connect().await?;
+
+        sqlx::query(QUERY_0).execute(&mut *conn).await?;
+
+        sqlx::query(QUERY_1)
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_all(&self, user_id: i64, now: models::DateTime) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "UPDATE users \
+                             SET tokens_revoked_at = $1 \
+                             WHERE id = $2";
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY).bind(now).bind(user_id).execute(&mut *conn).await?;
+
+        if result.rows_affected() != 1 {
+            anyhow::bail!("failed to update users");
+        }
+
+        Ok(())
+    }
+}
+
+pub trait FamilyRepository {
+    async fn start_family(
+        &self,
+        family: &str,
+        user_id: i64,
+        jti: &str,
+        expires_at: models::DateTime,
+    ) -> anyhow::Result<()>;
+    /// replaces `from` by `to` only if `from` is current, otherwise revokes the whole family
+    async fn rotate_family(
+        &self,
+        family: &str,
+        from: &str,
+        to: &str,
+        expires_at: models::DateTime,
+    ) -> anyhow::Result<bool>;
+    async fn revoke_family(&self, family: &str) -> anyhow::Result<()>;
+}
+
+impl<C: Connect> FamilyRepository for C {
+    async fn start_family(
+        &self,
+        family: &str,
+        user_id: i64,
+        jti: &str,
+        expires_at: models::DateTime,
+    ) -> anyhow::Result<()> {
+        // expired ones are rejected by their tokens
+        #[rustfmt::skip]
+        const QUERY_0: &str = "DELETE FROM token_families \
+                               WHERE expires_at < NOW() AT TIME ZONE 'UTC'";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "INSERT INTO token_families (id, user_id, current_jti, expires_at) \
+                               VALUES ($1, $2, $3, $4)";
+
+        let mut conn = self.connect().await?;
+
+        sqlx::query(QUERY_0).execute(&mut *conn).await?;
+
+        sqlx::query(QUERY_1)
+            .bind(family)
+            .bind(user_id)
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn rotate_family(
+        &self,
+        family: &str,
+        from: &str,
+        to: &str,
+        expires_at: models::DateTime,
+    ) -> anyhow::Result<bool> {
+        #[rustfmt::skip]
+        const QUERY_0: &str = "UPDATE token_families \
+                               SET current_jti = $3, expires_at = $4 \
+                               WHERE id = $1 AND current_jti = $2 AND revoked_at IS NULL";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "UPDATE token_families \
+                               SET revoked_at = NOW() AT TIME ZONE 'UTC' \
+                               WHERE id = $1 AND revoked_at IS NULL";
+
+        let mut conn = self.connect().await?;
+
+        let result = sqlx::query(QUERY_0)
+            .bind(family)
+            .bind(from)
+            .bind(to)
+            .bind(expires_at)
+            .execute(&mut *conn)
+            .await?;
+
+        if result.rows_affected() == 1 {
+            return Ok(true);
+        }
+
+        sqlx::query(QUERY_1).bind(family).execute(&mut *conn).await?;
+
+        Ok(false)
+    }
+
+    async fn revoke_family(&self, family: &str) -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "UPDATE token_families \
+                             SET revoked_at = NOW() AT TIME ZONE 'UTC' \
+                             WHERE id = $1 AND revoked_at IS NULL";
+
+        let mut conn = self.connect().await?;
+
+        sqlx::query(QUERY).bind(family).execute(&mut *conn).await?;
+
+        Ok(())
+    }
 }
@@ -1,4 +1,6 @@
 mod cookies;
+mod csrf;
+mod limit;
 mod models;
 
 mod uses {
@@ -10,7 +12,10 @@ mod uses {
     // crate: models
     pub use crate::models::{self, FromModel as _};
     // crate: repositories
-    pub use crate::repos::{KeyRepository, PostRepository};
+    pub use crate::repos::{
+        FamilyRepository, InvitationRepository, KeyRepository, PostRepository, TokenRepository,
+        Transaction as _, Transactional, UserRepository,
+    };
     // crate: stores
     pub use crate::stores::{Entry, Store};
 
@@ -37,7 +42,7 @@ mod uses {
     // internal: handle cookie
     pub use super::cookies::{Apply as _, Cookies};
     // internal: models
-    pub use super::models::Post;
+    pub use super::models::{decode_cursor, Diff, Jwks, Key, Page, Post, SearchHit, Tag};
 }
 
 #[allow(clippy::wildcard_imports)]
@@ -46,33 +51,67 @@ mod posts;
 #[allow(clippy::wildcard_imports)]
 mod auth;
 
+#[allow(clippy::wildcard_imports)]
+mod tags;
+
 use webauthn_rs::prelude as wan;
 
 pub fn services<
-    PR: 'static + crate::repos::PostRepository,
-    KR: 'static + crate::repos::KeyRepository,
-    RS: 'static + crate::stores::Store<wan::PasskeyRegistration, Key = SessionId>,
+    PR: 'static + crate::repos::PostRepository + crate::repos::Transactional,
+    KR: 'static
+        + crate::repos::KeyRepository
+        + crate::repos::UserRepository
+        + crate::repos::InvitationRepository
+        + crate::repos::Transactional,
+    RS: 'static + crate::stores::Store<Registration, Key = SessionId>,
     AS: 'static + crate::stores::Store<wan::PasskeyAuthentication, Key = SessionId>,
+    TR: 'static + crate::repos::TokenRepository + crate::repos::FamilyRepository,
 >() -> impl actix_web::dev::HttpServiceFactory {
     use actix_web::{services, web};
 
     let posts = services![
         web::resource("/posts")
             .route(web::get().to(posts::get::<PR>))
-            .route(web::post().to(posts::create::<PR>)),
+            .route(web::post().to(posts::create::<PR, TR>))
+            .wrap_fn(|req, srv| limit::guard(req, srv, |l| &l.posts)),
+        // must precede `/posts/{id}`
+        web::resource("/posts/search").route(web::get().to(posts::search::get::<PR>)),
         web::resource("/posts/{id}")
             .route(web::get().to(posts::_id_::get::<PR>))
-            .route(web::patch().to(posts::_id_::update::<PR>)),
+            .route(web::patch().to(posts::_id_::update::<PR, TR>)),
+        web::resource("/posts/{id}/revisions")
+            .route(web::get().to(posts::_id_::revisions::get::<PR>)),
+        web::resource("/posts/{id}/revisions/{created_at}")
+            .route(web::get().to(posts::_id_::revisions::_created_at_::get::<PR>)),
+        web::resource("/posts/{id}/diff").route(web::get().to(posts::_id_::diff::get::<PR>)),
+    ];
+
+    let tags = services![
+        web::resource("/tags").route(web::get().to(tags::get::<PR>)),
+        web::resource("/tags/{tag}/posts").route(web::get().to(tags::_tag_::posts::get::<PR>)),
     ];
 
     let auth = services![
-        web::resource("/auth/register").route(web::post().to(auth::register::<KR, RS>)),
-        web::resource("/auth/claim").route(web::post().to(auth::claim::<KR, AS>)),
-        web::resource("/auth/refresh").route(web::get().to(auth::refresh)),
-        web::resource("/auth/check").route(web::get().to(auth::check)),
+        web::resource("/auth/register")
+            .route(web::post().to(auth::register::<KR, RS, TR>))
+            .wrap_fn(|req, srv| limit::guard(req, srv, |l| &l.auth)),
+        web::resource("/auth/invitations").route(web::post().to(auth::invite::<KR, TR>)),
+        web::resource("/auth/keys").route(web::get().to(auth::keys::get::<KR, TR>)),
+        web::resource("/auth/keys/{id}")
+            .route(web::patch().to(auth::keys::_id_::update::<KR, TR>))
+            .route(web::delete().to(auth::keys::_id_::delete::<KR, TR>)),
+        web::resource("/auth/claim")
+            .route(web::post().to(auth::claim::<KR, AS, TR>))
+            .wrap_fn(|req, srv| limit::guard(req, srv, |l| &l.auth)),
+        web::resource("/auth/refresh").route(web::post().to(auth::refresh::<TR>)),
+        web::resource("/auth/check").route(web::get().to(auth::check::<TR>)),
+        web::resource("/auth/logout").route(web::post().to(auth::logout::<TR>)),
+        web::resource("/auth/logout/all").route(web::post().to(auth::logout_all::<TR>)),
+        web::resource("/auth/jwks").route(web::get().to(auth::jwks)),
     ];
 
-    services![posts, auth]
+    web::scope("").wrap_fn(csrf::guard).service(services![posts, tags, auth])
 }
 
-pub use auth::SessionId;
+pub use auth::{Registration, SessionId};
+pub use limit::Limiters;
@@ -1,6 +1,7 @@
 use webauthn_rs::prelude as wan;
 
-use crate::auth::Token;
+use crate::auth::{Authentication, Keyset, Token};
+use crate::config::Config;
 use crate::routes::uses::*;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -19,92 +20,399 @@ impl From<u128> for SessionId {
     fn from(val: u128) -> Self { Self(val) }
 }
 
-pub async fn register<KR: KeyRepository, RS: Store<wan::PasskeyRegistration, Key = SessionId>>(
+/// how long states of ceremonies are kept, abandoned ones are swept after this
+const CEREMONY_TTL: core::time::Duration = core::time::Duration::from_mins(5);
+
+/// state of registration ceremony, kept between its start and finish
+#[derive(Serialize, Deserialize)]
+pub struct Registration {
+    state: wan::PasskeyRegistration,
+    registrant: Registrant,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Registrant {
+    Existing(i64),
+    New {
+        name: String,
+        handle: wan::Uuid,
+        // `None` on bootstrap
+        invitation: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Register {
+    Finish(wan::RegisterPublicKeyCredential),
+
+    #[serde(rename_all = "camelCase")]
+    Start {
+        name: String,
+        invitation: Option<String>,
+    },
+}
+
+const FORBIDDEN: &str = "registration requires a session or an invitation";
+const BUSY: &str = "too many ceremonies in progress, retry later";
+
+/// who is registering, checked by the policy of registration
+async fn registrant<KR: KeyRepository + UserRepository + InvitationRepository, TR>(
+    repo: &KR,
+    ck: &Cookies<TR>,
+    data: Option<&Register>,
+) -> Result<Registrant, actix_web::Error> {
+    use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorForbidden};
+    use actix_web::error::ErrorInternalServerError as internal;
+
+    let registrant = match (&ck.session, data) {
+        // adding a passkey to signed in user
+        (Some(session), _) => Registrant::Existing(session.user_id().map_err(internal)?),
+
+        // anyone can register the first user
+        (None, start) if repo.all_keys().await.map_err(internal)?.is_empty() => match start {
+            Some(Register::Start { name, .. }) => Registrant::New {
+                name: name.clone(),
+                handle: wan::Uuid::new_v4(),
+                invitation: None,
+            },
+            _ => return Err(ErrorBadRequest("name is required")),
+        },
+
+        (None, Some(Register::Start { name, invitation: Some(code) })) => {
+            let now = chrono::Local::now().naive_utc();
+
+            match repo.find_invitation(code).await.map_err(internal)? {
+                Some(i) if i.is_available(now) => Registrant::New {
+                    name: name.clone(),
+                    handle: wan::Uuid::new_v4(),
+                    invitation: Some(code.clone()),
+                },
+                _ => return Err(ErrorForbidden(FORBIDDEN)),
+            }
+        },
+
+        (None, _) => return Err(ErrorForbidden(FORBIDDEN)),
+    };
+
+    if let Registrant::New { ref name, .. } = registrant {
+        if repo.find_user_by_name(name).await.map_err(internal)?.is_some() {
+            return Err(ErrorConflict("name is already taken"));
+        }
+    }
+
+    Ok(registrant)
+}
+
+pub async fn register<
+    KR: KeyRepository + UserRepository + InvitationRepository + Transactional,
+    RS: Store<Registration, Key = SessionId>,
+    TR: TokenRepository,
+>(
     repo: web::Data<KR>,
     store: web::Data<RS>,
     site: web::Data<wan::Webauthn>,
-    data: web::Json<Option<wan::RegisterPublicKeyCredential>>,
-    mut ck: Cookies,
+    data: web::Json<Option<Register>>,
+    mut ck: Cookies<TR>,
 ) -> impl Responder {
     try_into_responder!({
         match (ck.status, &*data) {
-            (None | Some(_), None) => {
-                let excludes = repo
-                    .all()
-                    .await?
-                    .into_iter()
-                    .map(|p| p.cred_id().clone())
-                    .collect::<Vec<_>>();
-
-                let (ccr, pr) = site.start_passkey_registration(
-                    wan::Uuid::new_v4(),
-                    "owner",
-                    "owner",
-                    Some(excludes),
-                )?;
+            (None | Some(_), None | Some(Register::Start { .. })) => {
+                let registrant = registrant(&**repo, &ck, data.as_ref()).await?;
+
+                let (handle, name, excludes) = match registrant {
+                    Registrant::Existing(id) => {
+                        let user = repo
+                            .find_user(id)
+                            .await?
+                            .ok_or_else(|| actix_web::error::ErrorUnauthorized("user isn't found"))?;
+
+                        let excludes = repo
+                            .find_by_user(id)
+                            .await?
+                            .into_iter()
+                            .map(|k| k.passkey.cred_id().clone())
+                            .collect::<Vec<_>>();
+
+                        (user.handle, user.name, excludes)
+                    },
+                    Registrant::New {
+                        ref name, handle, ..
+                    } => (handle, name.clone(), vec![]),
+                };
+
+                let (ccr, state) =
+                    site.start_passkey_registration(handle, &name, &name, Some(excludes))?;
 
                 let id = SessionId::new();
 
-                assert!(store.entry(id).await?.set(pr).await?);
+                let registration = Registration { state, registrant };
+                // id is random, so it's rejected only if the store is full
+                if !store.entry(id).await?.set_with_ttl(registration, CEREMONY_TTL).await? {
+                    return HttpResponse::ServiceUnavailable().body(BUSY);
+                }
 
                 ck.status.replace(id);
 
                 HttpResponse::Accepted().apply_cookies(ck)?.json(ccr)
             },
 
-            (Some(id), Some(data)) => {
-                let pr =
-                    store.entry(id).await?.get().await?.ok_or_else(|| {
+            (Some(id), Some(Register::Finish(data))) => {
+                let Registration { state, registrant } =
+                    store.entry(id).await?.take().await?.ok_or_else(|| {
                         actix_web::error::ErrorBadRequest("registration isn't found")
                     })?;
 
-                let result = site.finish_passkey_registration(data, &pr);
-
-                if let Ok(ref p) = result {
-                    repo.push(rand::random(), p.clone()).await?;
-                }
+                let result = site.finish_passkey_registration(data, &state);
 
                 ck.status.take();
 
+                if let Ok(ref passkey) = result {
+                    let tx = repo.begin().await?;
+
+                    let now = chrono::Local::now().naive_utc();
+
+                    let user_id = match registrant {
+                        Registrant::Existing(id) => id,
+                        Registrant::New {
+                            name,
+                            handle,
+                            invitation,
+                        } => {
+                            // policy is checked again, as the state may change during ceremony
+                            let is_allowed = if let Some(ref code) = invitation {
+                                tx.consume_invitation(code, now).await?
+                            } else {
+                                // serialized, or concurrent ones may create first users both
+                                tx.lock_keys().await?;
+                                tx.all_keys().await?.is_empty()
+                            };
+
+                            if !is_allowed {
+                                return HttpResponse::Forbidden()
+                                    .apply_cookies(ck)?
+                                    .body(FORBIDDEN);
+                            }
+
+                            tx.create_user(name, handle, now).await?
+                        },
+                    };
+
+                    let key = models::Key {
+                        id: rand::random(),
+                        user_id,
+                        passkey: passkey.clone(),
+                        counter: 0,
+                        nickname: None,
+                        created_at: now,
+                        last_used_at: None,
+                    };
+
+                    tx.push(key).await?;
+                    tx.commit().await?;
+                }
+
                 HttpResponse::Ok().apply_cookies(ck)?.json(result.is_ok())
             },
 
-            (None, Some(_)) => HttpResponse::BadRequest().finish(),
+            (None, Some(Register::Finish(_))) => HttpResponse::BadRequest().finish(),
         }
     })
 }
 
-pub async fn claim<KR: KeyRepository, AS: Store<wan::PasskeyAuthentication, Key = SessionId>>(
+const INVITATION_LIFETIME: chrono::Duration = chrono::Duration::days(7);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Invited {
+    code: String,
+    expires_at: models::DateTime,
+}
+
+pub async fn invite<IR: InvitationRepository, TR: TokenRepository>(
+    repo: web::Data<IR>,
+    ck: Cookies<TR>,
+) -> impl Responder {
+    try_into_responder!({
+        let Some(ref session) = ck.session else {
+            return HttpResponse::Unauthorized().finish();
+        };
+
+        let now = chrono::Local::now().naive_utc();
+
+        let model = models::Invitation {
+            code: format!("{:032x}", rand::random::<u128>()),
+            issued_by: session.user_id()?,
+            created_at: now,
+            expires_at: now + INVITATION_LIFETIME,
+            used_at: None,
+        };
+
+        let invited = Invited {
+            code: model.code.clone(),
+            expires_at: model.expires_at,
+        };
+
+        repo.issue_invitation(model).await?;
+
+        HttpResponse::Ok().json(invited)
+    })
+}
+
+pub mod keys {
+    use crate::routes::uses::*;
+
+    pub async fn get<KR: KeyRepository, TR: TokenRepository>(
+        repo: web::Data<KR>,
+        ck: Cookies<TR>,
+    ) -> impl Responder {
+        try_into_responder!({
+            let Some(ref session) = ck.session else {
+                return HttpResponse::Unauthorized().finish();
+            };
+
+            let jsons = repo
+                .find_by_user(session.user_id()?)
+                .await?
+                .into_iter()
+                .map(Key::from_model)
+                .try_collect::<Vec<_>>()?;
+
+            HttpResponse::Ok().json(jsons)
+        })
+    }
+
+    pub mod _id_ {
+        use crate::routes::uses::*;
+
+        /// finds the key, only if it's owned by signed in user
+        async fn find_own<KR: KeyRepository, TR>(
+            repo: &KR,
+            id: u32,
+            ck: &Cookies<TR>,
+        ) -> Result<models::Key, actix_web::Error> {
+            use actix_web::error::{ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized};
+
+            let session = ck
+                .session
+                .as_ref()
+                .ok_or_else(|| ErrorUnauthorized("session is required"))?;
+
+            let user_id = session.user_id().map_err(ErrorInternalServerError)?;
+
+            repo.get(id)
+                .await
+                .map_err(ErrorInternalServerError)?
+                .filter(|k| k.user_id == user_id)
+                .ok_or_else(|| ErrorNotFound("key isn't found"))
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub struct Update {
+            pub nickname: Option<String>,
+        }
+
+        pub async fn update<KR: KeyRepository, TR: TokenRepository>(
+            repo: web::Data<KR>,
+            id: web::Path<u32>,
+            data: web::Json<Update>,
+            ck: Cookies<TR>,
+        ) -> impl Responder {
+            try_into_responder!({
+                let key = find_own(&**repo, *id, &ck).await?;
+
+                let Update { nickname } = data.into_inner();
+                repo.rename(key.id, nickname).await?;
+
+                let model = repo.get(key.id).await?;
+                HttpResponse::Ok().json(model.map(Key::from_model).transpose()?)
+            })
+        }
+
+        pub async fn delete<KR: KeyRepository, TR: TokenRepository>(
+            repo: web::Data<KR>,
+            id: web::Path<u32>,
+            ck: Cookies<TR>,
+        ) -> impl Responder {
+            try_into_responder!({
+                let key = find_own(&**repo, *id, &ck).await?;
+
+                // keeps at least one key, otherwise the user can't sign in anymore
+                if repo.find_by_user(key.user_id).await?.len() <= 1 {
+                    return HttpResponse::Conflict().body("the last key can't be removed");
+                }
+
+                repo.remove(key.id).await?;
+
+                HttpResponse::NoContent().finish()
+            })
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Claim {
+    Finish(wan::PublicKeyCredential),
+
+    #[serde(rename_all = "camelCase")]
+    Start { name: String },
+}
+
+pub async fn claim<
+    KR: KeyRepository + UserRepository,
+    AS: Store<wan::PasskeyAuthentication, Key = SessionId>,
+    TR: TokenRepository + FamilyRepository,
+>(
     repo: web::Data<KR>,
     store: web::Data<AS>,
+    tokens: web::Data<TR>,
     site: web::Data<wan::Webauthn>,
-    data: web::Json<Option<wan::PublicKeyCredential>>,
-    mut ck: Cookies,
+    config: web::Data<Config>,
+    data: web::Json<Option<Claim>>,
+    mut ck: Cookies<TR>,
 ) -> impl Responder {
     try_into_responder!({
-        if let Some(ref mut token) = ck.refresh {
-            *token = Token::issue_refresh();
+        // falls back to authentication with passkeys if the token can't be rotated
+        if let Some(refresh) = ck.refresh.take() {
+            if let Some(next) = rotate(&**tokens, &config, &refresh).await? {
+                ck.refresh.replace(next);
 
-            return HttpResponse::Ok().apply_cookies(ck)?.finish();
+                return HttpResponse::Ok().apply_cookies(ck)?.finish();
+            }
         }
 
         match (ck.status, &*data) {
-            (None | Some(_), None) => {
-                let keys = repo.all().await?;
+            (None | Some(_), Some(Claim::Start { name })) => {
+                // only keys of the user are allowed, others' credentials aren't exposed
+                let keys = match repo.find_user_by_name(name).await? {
+                    Some(user) => repo.find_by_user(user.id).await?,
+                    None => vec![],
+                };
+
+                if keys.is_empty() {
+                    return HttpResponse::Unauthorized().finish();
+                }
+
+                let keys = keys.into_iter().map(|k| k.passkey).collect::<Vec<_>>();
 
                 let (rcr, pa) = site.start_passkey_authentication(&keys)?;
 
                 let id = SessionId::new();
 
-                assert!(store.entry(id).await?.set(pa).await?);
+                // id is random, so it's rejected only if the store is full
+                if !store.entry(id).await?.set_with_ttl(pa, CEREMONY_TTL).await? {
+                    return HttpResponse::ServiceUnavailable().body(BUSY);
+                }
 
                 ck.status.replace(id);
 
                 HttpResponse::Accepted().apply_cookies(ck)?.json(rcr)
             },
 
-            (Some(id), Some(data)) => {
-                let pa = match store.entry(id).await?.get().await? {
+            (Some(id), Some(Claim::Finish(data))) => {
+                let pa = match store.entry(id).await?.take().await? {
                     None => return HttpResponse::BadRequest().finish(),
                     Some(pa) => pa,
                 };
@@ -113,35 +421,150 @@ pub async fn claim<KR: KeyRepository, AS: Store<wan::PasskeyAuthentication, Key
 
                 ck.status.take();
 
-                match result {
-                    Ok(_) => {
-                        let token = Token::issue_refresh();
-                        ck.refresh.replace(token);
+                let Ok(result) = result else {
+                    return HttpResponse::Unauthorized().finish();
+                };
 
-                        HttpResponse::Ok().apply_cookies(ck)?.finish()
-                    },
-                    Err(_) => HttpResponse::Unauthorized().finish(),
+                // the key may be removed during authentication
+                let Some(mut key) = repo.find_by_cred(result.cred_id().as_ref()).await? else {
+                    return HttpResponse::Unauthorized().apply_cookies(ck)?.finish();
+                };
+
+                // both are `0` if the authenticator doesn't support counter
+                let counter = result.counter();
+                if (counter != 0 || key.counter != 0) && counter <= key.counter {
+                    tracing::warn!(key = key.id, counter, "counter is regressed, maybe cloned");
+
+                    return HttpResponse::Unauthorized().apply_cookies(ck)?.finish();
                 }
+
+                key.passkey
+                    .update_credential(&result)
+                    .ok_or_else(|| anyhow::anyhow!("credential of authenticated key is unmatched"))?;
+
+                key.counter = counter;
+                key.last_used_at = Some(chrono::Local::now().naive_utc());
+
+                let user_id = key.user_id;
+                let auth = Authentication::with_passkey(key.id, result.user_verified());
+                repo.update_key(key).await?;
+
+                let token = Token::issue_refresh(&config, user_id, auth);
+                let family = token
+                    .family()
+                    .ok_or_else(|| anyhow::anyhow!("issued refresh token has no family"))?;
+
+                tokens
+                    .start_family(family, user_id, token.jti(), token.expires_at().into())
+                    .await?;
+
+                ck.refresh.replace(token);
+
+                HttpResponse::Ok().apply_cookies(ck)?.finish()
             },
 
-            (None, Some(_)) => HttpResponse::BadRequest().finish(),
+            (_, None) | (None, Some(Claim::Finish(_))) => HttpResponse::BadRequest().finish(),
         }
     })
 }
 
-pub async fn refresh(mut ck: Cookies) -> impl Responder {
+pub async fn refresh<TR: TokenRepository + FamilyRepository>(
+    tokens: web::Data<TR>,
+    config: web::Data<Config>,
+    mut ck: Cookies<TR>,
+) -> impl Responder {
     try_into_responder!({
-        match ck.refresh {
-            None => HttpResponse::Unauthorized().finish(),
-            Some(_) => {
-                let token = Token::issue_session();
-                ck.session.replace(token);
-                HttpResponse::Ok().apply_cookies(ck)?.finish()
-            },
+        // rotates only after the session is expired, otherwise concurrent requests would present
+        // the same refresh token, and it's taken as reused
+        if ck.session.is_some() {
+            return HttpResponse::Ok().finish();
         }
+
+        let Some(refresh) = ck.refresh.take() else {
+            return HttpResponse::Unauthorized().finish();
+        };
+
+        let Some(next) = rotate(&**tokens, &config, &refresh).await? else {
+            return HttpResponse::Unauthorized().apply_cookies(ck)?.finish();
+        };
+
+        let token = next.issue_session(&config)?;
+        ck.session.replace(token);
+        ck.refresh.replace(next);
+
+        HttpResponse::Ok().apply_cookies(ck)?.finish()
     })
 }
 
+/// uses up the refresh token, and issues the next one in its family
+///
+/// presenting a token which is already used revokes the whole family, since either of the
+/// holders is not the legitimate one
+async fn rotate<TR: FamilyRepository>(
+    tokens: &TR,
+    config: &Config,
+    refresh: &Token,
+) -> anyhow::Result<Option<Token>> {
+    // tokens issued before families are introduced can't be rotated
+    let (Some(next), Some(family)) = (refresh.rotate(config)?, refresh.family()) else {
+        return Ok(None);
+    };
+
+    let (from, to) = (refresh.jti(), next.jti());
+    let expires_at = next.expires_at().into();
+
+    if !tokens.rotate_family(family, from, to, expires_at).await? {
+        tracing::warn!(family, "refresh token is reused, revoked its family");
+
+        return Ok(None);
+    }
+
+    Ok(Some(next))
+}
+
+/// revokes the tokens of this request
+pub async fn logout<TR: TokenRepository + FamilyRepository>(
+    repo: web::Data<TR>,
+    mut ck: Cookies<TR>,
+) -> impl Responder {
+    try_into_responder!({
+        for token in [ck.refresh.take(), ck.session.take()].into_iter().flatten() {
+            repo.revoke(token.jti(), token.expires_at().into()).await?;
+
+            if let Some(family) = token.family() {
+                repo.revoke_family(family).await?;
+            }
+        }
+
+        HttpResponse::Ok().apply_cookies(ck)?.finish()
+    })
+}
+
+/// revokes all tokens of the user, issued until now
+pub async fn logout_all<TR: TokenRepository>(
+    repo: web::Data<TR>,
+    mut ck: Cookies<TR>,
+) -> impl Responder {
+    try_into_responder!({
+        let (refresh, session) = (ck.refresh.take(), ck.session.take());
+
+        let Some(token) = refresh.or(session) else {
+            return HttpResponse::Unauthorized().finish();
+        };
+
+        // same precision as `iat`, so tokens issued earlier in this second are revoked too
+        let now = chrono::SubsecRound::trunc_subsecs(chrono::Local::now().naive_utc(), 0);
+        repo.revoke_all(token.user_id()?, now).await?;
+
+        HttpResponse::Ok().apply_cookies(ck)?.finish()
+    })
+}
+
+/// publishes public keys verifying tokens
+pub async fn jwks(keys: web::Data<Keyset>) -> impl Responder {
+    HttpResponse::Ok().json(Jwks::from_keyset(&keys))
+}
+
 #[derive(Serialize)]
 struct Checked {
     refresh: bool,
@@ -149,7 +572,7 @@ struct Checked {
     status: bool,
 }
 
-pub async fn check(ck: Cookies) -> impl Responder {
+pub async fn check<TR: TokenRepository>(ck: Cookies<TR>) -> impl Responder {
     let result = Checked {
         refresh: ck.refresh.is_some(),
         session: ck.session.is_some(),
@@ -1,108 +1,152 @@
 use core::future::Future;
+use core::marker::PhantomData;
 
-use actix_web::cookie::Cookie;
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use actix_web::cookie::{Cookie, CookieBuilder};
 use actix_web::dev::Payload;
-use actix_web::{Error, FromRequest, HttpRequest};
+use actix_web::{web, Error, FromRequest, HttpRequest};
 
 use super::SessionId;
-use crate::auth::Token;
+use crate::auth::{Keyset, NumericDate, Token};
+use crate::config::Config;
+use crate::repos::TokenRepository;
 
-pub struct Cookies {
+/// cookies of request, tokens in them are verified and checked whether revoked by `TR`
+pub struct Cookies<TR> {
     pub refresh: Option<Token>,
     pub session: Option<Token>,
     pub status: Option<SessionId>,
+    keys: web::Data<Keyset>,
+    config: web::Data<Config>,
+    _repo: PhantomData<fn() -> TR>,
 }
 
-impl FromRequest for Cookies {
+impl<TR: 'static + TokenRepository> FromRequest for Cookies<TR> {
     type Error = Error;
 
     type Future = impl Future<Output = Result<Self, Self::Error>>;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        let result: Result<Self, Self::Error> = try {
-            let refresh = req
-                .cookie("refresh")
-                .and_then(|c| Token::decode(c.value()).ok())
-                .and_then(|t| t.is_refresh().then_some(t));
-
-            let session = req
-                .cookie("session")
-                .and_then(|c| Token::decode(c.value()).ok())
-                .and_then(|t| t.is_session().then_some(t));
-
-            let status = req
-                .cookie("status")
-                .and_then(|c| u128::from_str_radix(c.value(), 16).ok())
-                .map(SessionId::from);
-
-            Self {
-                refresh,
-                session,
-                status,
-            }
+        let keys = req.app_data::<web::Data<Keyset>>().cloned();
+        let config = req.app_data::<web::Data<Config>>().cloned();
+
+        let decode = |name: &str| -> Option<Token> {
+            let (keys, config) = (keys.as_deref()?, config.as_deref()?);
+
+            req.cookie(name).and_then(|c| Token::decode(c.value(), keys, config).ok())
         };
 
-        async { result }
+        let refresh = decode("refresh").and_then(|t| t.is_refresh().then_some(t));
+        let session = decode("session").and_then(|t| t.is_session().then_some(t));
+
+        let status = req
+            .cookie("status")
+            .and_then(|c| u128::from_str_radix(c.value(), 16).ok())
+            .map(SessionId::from);
+
+        let repo = req.app_data::<web::Data<TR>>().cloned();
+
+        async move {
+            use actix_web::error::ErrorInternalServerError;
+
+            let result: anyhow::Result<Self> = try {
+                let keys = keys.ok_or_else(|| anyhow::anyhow!("keyset isn't found"))?;
+                let config = config.ok_or_else(|| anyhow::anyhow!("config isn't found"))?;
+                let repo = repo.ok_or_else(|| anyhow::anyhow!("token repository isn't found"))?;
+
+                Self {
+                    refresh: unrevoked(&**repo, refresh).await?,
+                    session: unrevoked(&**repo, session).await?,
+                    status,
+                    keys,
+                    config,
+                    _repo: PhantomData,
+                }
+            };
+
+            result.map_err(|any| {
+                tracing::error!(%any, "failed to check tokens");
+                ErrorInternalServerError("failed to check tokens")
+            })
+        }
     }
 }
 
-impl Cookies {
+async fn unrevoked<TR: TokenRepository>(
+    repo: &TR,
+    token: Option<Token>,
+) -> anyhow::Result<Option<Token>> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+
+    let is_revoked = repo
+        .is_revoked(token.jti(), token.user_id()?, token.key_id(), token.issued_at().into())
+        .await?;
+
+    Ok((!is_revoked).then_some(token))
+}
+
+impl<TR> Cookies<TR> {
     pub fn as_cookies(&self) -> anyhow::Result<impl Iterator<Item = Cookie>> {
-        use actix_web::cookie::SameSite;
-
-        let refresh = if let Some(ref token) = self.refresh {
-            Cookie::build("refresh", token.encode()?)
-                .domain(*crate::vars::SERVE_HOST)
-                .same_site(SameSite::Strict)
-                .http_only(true)
-                .path("/-api/")
-                .secure(true)
-                .finish()
-        } else {
-            let mut c = Cookie::new("refresh", "");
-            c.make_removal();
-
-            c
+        let token = |name: &'static str, token: &Option<Token>| -> anyhow::Result<Cookie<'static>> {
+            let Some(token) = token else {
+                return Ok(self.removal(name));
+            };
+
+            let exp = token.expires_at().as_secs();
+            let now = NumericDate::now().as_secs();
+
+            let expires = OffsetDateTime::from_unix_timestamp(i64::try_from(exp)?)?;
+            let max_age = Duration::seconds(i64::try_from(exp.saturating_sub(now))?);
+
+            Ok(self
+                .build(name, token.encode(&self.keys)?)
+                .expires(expires)
+                .max_age(max_age)
+                .finish())
         };
 
-        let session = if let Some(ref token) = self.session {
-            Cookie::build("session", token.encode()?)
-                .domain(*crate::vars::SERVE_HOST)
-                .same_site(SameSite::Strict)
-                .http_only(true)
-                .path("/-api/")
-                .secure(true)
-                .finish()
-        } else {
-            let mut c = Cookie::new("session", "");
-            c.make_removal();
-
-            c
-        };
+        let refresh = token("refresh", &self.refresh)?;
+        let session = token("session", &self.session)?;
 
-        let status = if let Some(ref status) = self.status {
-            Cookie::build("status", status.to_string())
-                .domain(*crate::vars::SERVE_HOST)
-                .same_site(SameSite::Strict)
-                .http_only(true)
-                .path("/-api/")
-                .secure(true)
-                .finish()
-        } else {
-            let mut c = Cookie::new("status", "");
-            c.make_removal();
-
-            c
-        };
+        let status = self.status.as_ref().map_or_else(
+            || self.removal("status"),
+            |status| self.build("status", status.to_string()).finish(),
+        );
 
         Ok([refresh, session, status].into_iter())
     }
+
+    fn build(&self, name: &'static str, value: String) -> CookieBuilder<'static> {
+        let config = &self.config.cookies;
+
+        let mut builder = Cookie::build(name, value)
+            .same_site(config.same_site)
+            .http_only(true)
+            .path(config.path.clone())
+            .secure(config.secure);
+
+        if let Some(ref domain) = config.domain {
+            builder = builder.domain(domain.clone());
+        }
+
+        builder
+    }
+
+    /// attributes must be same as the cookie to remove
+    fn removal(&self, name: &'static str) -> Cookie<'static> {
+        let mut cookie = self.build(name, String::new()).finish();
+        cookie.make_removal();
+
+        cookie
+    }
 }
 
 pub trait Apply {
     fn apply_cookie(&mut self, cookie: Cookie) -> &mut Self;
 
-    fn apply_cookies(&mut self, ck: Cookies) -> anyhow::Result<&mut Self> {
+    fn apply_cookies<TR>(&mut self, ck: Cookies<TR>) -> anyhow::Result<&mut Self> {
         Ok(ck.as_cookies()?.fold(self, |s, c| s.apply_cookie(c)))
     }
 }
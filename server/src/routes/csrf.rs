@@ -0,0 +1,54 @@
+use core::future::Future;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{web, Error};
+
+use crate::config::Config;
+
+/// rejects state-changing requests from other sites, for `App::wrap_fn` / `Scope::wrap_fn`
+///
+/// cookies can't be trusted by `SameSite` alone, when the client is served through a proxy
+pub fn guard<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let is_allowed = req
+        .app_data::<web::Data<Config>>()
+        .is_some_and(|config| is_allowed(&req, config));
+
+    if !is_allowed {
+        let origin = req.headers().get("origin").and_then(|o| o.to_str().ok());
+        tracing::warn!(?origin, path = req.path(), "rejected cross-site request");
+    }
+
+    let fut = is_allowed.then(|| srv.call(req));
+
+    async move {
+        match fut {
+            Some(fut) => fut.await,
+            None => Err(actix_web::error::ErrorForbidden("cross-site request is rejected")),
+        }
+    }
+}
+
+fn is_allowed(req: &ServiceRequest, config: &Config) -> bool {
+    if req.method().is_safe() {
+        return true;
+    }
+
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    // sent by all modern browsers, trusted as-is only when it's from the same origin
+    if header("sec-fetch-site") == Some("same-origin") {
+        return true;
+    }
+
+    header("origin").map_or_else(
+        // not from browsers, which send `Origin` with all state-changing requests
+        || header("sec-fetch-site").is_none(),
+        |origin| config.cors.origins.iter().any(|o| o == origin),
+    )
+}
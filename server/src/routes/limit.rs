@@ -0,0 +1,198 @@
+use core::future::Future;
+use core::time::Duration;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{web, Error, HttpResponse};
+use dashmap::DashMap;
+
+use crate::config;
+
+/// rate limiters shared by all workers, limiting state-changing requests
+pub struct Limiters {
+    trust_proxy: bool,
+    pub(super) auth: Limiter,
+    pub(super) posts: Limiter,
+}
+
+impl Limiters {
+    pub fn new(config: &config::Limits) -> Self {
+        Self {
+            trust_proxy: config.trust_proxy,
+            auth: Limiter::new(config.auth),
+            posts: Limiter::new(config.posts),
+        }
+    }
+}
+
+/// token buckets for each IP and for all, both refill their capacity in a minute
+pub struct Limiter {
+    config: config::Limit,
+    global: Mutex<Bucket>,
+    per_ip: DashMap<IpAddr, Bucket>,
+    checked: AtomicU64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Limiter {
+    /// buckets of IPs are swept in this interval of checks
+    const SWEEP_INTERVAL: u64 = 1024;
+
+    fn new(config: config::Limit) -> Self {
+        Self {
+            config,
+            global: Mutex::new(Bucket::new(config.global)),
+            per_ip: DashMap::new(),
+            checked: AtomicU64::new(0),
+        }
+    }
+
+    /// takes a token from buckets, or returns how long to wait
+    fn check(&self, ip: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        if self.checked.fetch_add(1, Ordering::Relaxed).is_multiple_of(Self::SWEEP_INTERVAL) {
+            let capacity = self.config.per_ip;
+            self.per_ip.retain(|_, b| !b.is_full(capacity, now));
+        }
+
+        if let Some(ip) = ip {
+            let capacity = self.config.per_ip;
+            let mut bucket = self.per_ip.entry(ip).or_insert_with(|| Bucket::new(capacity));
+
+            bucket.take(capacity, now)?;
+        }
+
+        let mut global = self.global.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        global.take(self.config.global, now)
+    }
+}
+
+impl Bucket {
+    fn new(capacity: u32) -> Self {
+        Self {
+            tokens: f64::from(capacity),
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, capacity: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        let capacity = f64::from(capacity);
+
+        self.tokens = capacity.min(self.tokens + elapsed * capacity / 60.0);
+        self.updated_at = now;
+    }
+
+    fn is_full(&mut self, capacity: u32, now: Instant) -> bool {
+        self.refill(capacity, now);
+
+        self.tokens >= f64::from(capacity)
+    }
+
+    fn take(&mut self, capacity: u32, now: Instant) -> Result<(), Duration> {
+        // `0` is unlimited
+        if capacity == 0 {
+            return Ok(());
+        }
+
+        self.refill(capacity, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            return Ok(());
+        }
+
+        let wait = (1.0 - self.tokens) * 60.0 / f64::from(capacity);
+
+        Err(Duration::from_secs_f64(wait))
+    }
+}
+
+/// limits state-changing requests by the limiter, for `Resource::wrap_fn`
+pub fn guard<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    limiter: fn(&Limiters) -> &Limiter,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let result = match req.app_data::<web::Data<Limiters>>() {
+        _ if req.method().is_safe() => Ok(()),
+        None => Ok(()),
+        Some(limiters) => {
+            let info = req.connection_info();
+
+            let ip = if limiters.trust_proxy {
+                info.realip_remote_addr()
+            } else {
+                info.peer_addr()
+            };
+
+            // `Forwarded` may have port
+            let ip = ip.and_then(|ip| {
+                ip.parse::<IpAddr>()
+                    .or_else(|_| ip.parse::<std::net::SocketAddr>().map(|a| a.ip()))
+                    .ok()
+            });
+
+            limiter(limiters).check(ip)
+        },
+    };
+
+    let fut = result.map(|()| srv.call(req));
+
+    async move {
+        match fut {
+            Ok(fut) => fut.await,
+            Err(wait) => {
+                let res = HttpResponse::TooManyRequests()
+                    .insert_header((actix_web::http::header::RETRY_AFTER, retry_after(wait)))
+                    .finish();
+
+                Err(actix_web::error::InternalError::from_response("rate limited", res).into())
+            },
+        }
+    }
+}
+
+/// seconds of `Retry-After`, rounded up not to be retried too early
+fn retry_after(wait: Duration) -> u64 { wait.as_secs() + u64::from(wait.subsec_nanos() > 0) }
+
+#[test]
+fn bucket_refill() {
+    let mut bucket = Bucket::new(2);
+    let now = bucket.updated_at;
+
+    assert_eq!(bucket.take(2, now), Ok(()));
+    assert_eq!(bucket.take(2, now), Ok(()));
+
+    // 2 tokens per minute, a token is refilled in 30 seconds
+    assert_eq!(bucket.take(2, now).map_err(retry_after), Err(30));
+
+    let later = now + Duration::from_secs(15);
+    assert_eq!(bucket.take(2, later).map_err(retry_after), Err(15));
+
+    // waiting 29.5 seconds is rounded up
+    let later = now + Duration::from_millis(30_500);
+    assert_eq!(bucket.take(2, later), Ok(()));
+    assert_eq!(bucket.take(2, later).map_err(retry_after), Err(30));
+
+    // never exceeds the capacity
+    let later = now + Duration::from_hours(1);
+    assert!(bucket.is_full(2, later));
+    assert!(bucket.tokens <= 2.0);
+
+    // `0` is unlimited
+    let mut bucket = Bucket::new(0);
+    assert!((0..100).all(|_| bucket.take(0, now).is_ok()));
+}
@@ -1,9 +1,12 @@
+use crate::models::FromModel as _;
+
 pub type DateTime = chrono::NaiveDateTime;
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Post {
-    pub id: u32,
+    pub id: i64,
+    pub author_id: i64,
     pub content: PostContent,
     pub posted_at: DateTime,
     pub created_at: DateTime,
@@ -24,6 +27,7 @@ impl crate::models::FromModel for Post {
     where Self: Sized {
         let Self::Model {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
@@ -31,16 +35,30 @@ impl crate::models::FromModel for Post {
         } = model;
 
         let content = {
-            use pulldown_cmark::{html, Parser};
+            use crate::models::Fragment;
+            use pulldown_cmark::{html, Event};
+            use url::form_urlencoded::byte_serialize;
+
+            // hashtags are linked to the timeline of them, consisting of only word characters
+            let events = crate::models::markdown(&content).map(|f| match f {
+                Fragment::Event(e) => e,
+                Fragment::Hashtag { text, tag } => {
+                    let href = byte_serialize(tag.as_bytes()).collect::<String>();
+                    let a = format!(r#"<a class="hashtag" href="/?tag={href}">{text}</a>"#);
+
+                    Event::InlineHtml(a.into())
+                },
+            });
 
             let mut html = String::new();
-            html::push_html(&mut html, Parser::new(&content));
+            html::push_html(&mut html, events);
 
             PostContent { src: content, html }
         };
 
         Ok(Self {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
@@ -48,3 +66,328 @@ impl crate::models::FromModel for Post {
         })
     }
 }
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub name: String,
+    pub count: i64,
+}
+
+impl crate::models::FromModel for Tag {
+    type Model = crate::models::Tag;
+
+    fn from_model(model: Self::Model) -> anyhow::Result<Self>
+    where Self: Sized {
+        let Self::Model { name, count } = model;
+
+        Ok(Self { name, count })
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub post: Post,
+    pub rank: f32,
+    /// HTML of fragments, matches are surrounded by `<mark>`
+    pub snippet: String,
+}
+
+impl crate::models::FromModel for SearchHit {
+    type Model = crate::models::SearchHit;
+
+    fn from_model(model: Self::Model) -> anyhow::Result<Self>
+    where Self: Sized {
+        let Self::Model {
+            post,
+            rank,
+            headline,
+        } = model;
+
+        // headline isn't escaped by postgres, only sentinels are turned into tags
+        let (start, stop) = crate::models::SearchHit::HIGHLIGHT;
+        let snippet = headline.chars().fold(String::new(), |mut acc, c| {
+            match c {
+                '&' => acc.push_str("&amp;"),
+                '<' => acc.push_str("&lt;"),
+                '>' => acc.push_str("&gt;"),
+                '"' => acc.push_str("&quot;"),
+                '\'' => acc.push_str("&#39;"),
+                c if c == start => acc.push_str("<mark>"),
+                c if c == stop => acc.push_str("</mark>"),
+                c => acc.push(c),
+            }
+
+            acc
+        });
+
+        Ok(Self {
+            post: Post::from_model(post)?,
+            rank,
+            snippet,
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Key {
+    pub id: u32,
+    pub nickname: Option<String>,
+    pub credential_id: webauthn_rs::prelude::CredentialID,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl crate::models::FromModel for Key {
+    type Model = crate::models::Key;
+
+    fn from_model(model: Self::Model) -> anyhow::Result<Self>
+    where Self: Sized {
+        let Self::Model {
+            id,
+            user_id: _,
+            passkey,
+            counter: _,
+            nickname,
+            created_at,
+            last_used_at,
+        } = model;
+
+        Ok(Self {
+            id,
+            nickname,
+            credential_id: passkey.cred_id().clone(),
+            created_at,
+            last_used_at,
+        })
+    }
+}
+
+/// JSON Web Key Set (RFC 7517), of public keys verifying tokens
+#[derive(serde::Serialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub usage: &'static str,
+    pub kid: String,
+    pub x: String,
+}
+
+impl Jwks {
+    pub fn from_keyset(keys: &crate::auth::Keyset) -> Self {
+        use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD as engine};
+
+        let keys = keys
+            .public_keys()
+            .into_iter()
+            .map(|(kid, raw)| Jwk {
+                kty: "OKP",
+                crv: "Ed25519",
+                alg: "EdDSA",
+                usage: "sig",
+                kid,
+                x: engine.encode(raw),
+            })
+            .collect();
+
+        Self { keys }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diff {
+    pub lines: Vec<Hunk>,
+    pub words: Vec<Change>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub changes: Vec<Change>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub tag: ChangeTag,
+    pub value: String,
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeTag {
+    Equal,
+    Insert,
+    Delete,
+}
+
+impl From<similar::ChangeTag> for ChangeTag {
+    fn from(tag: similar::ChangeTag) -> Self {
+        match tag {
+            similar::ChangeTag::Equal => Self::Equal,
+            similar::ChangeTag::Insert => Self::Insert,
+            similar::ChangeTag::Delete => Self::Delete,
+        }
+    }
+}
+
+impl Diff {
+    const CONTEXT_LINES: usize = 3;
+
+    pub fn new(old: &str, new: &str) -> Self {
+        use similar::TextDiff;
+
+        let diff = TextDiff::from_lines(old, new);
+        let lines = diff
+            .grouped_ops(Self::CONTEXT_LINES)
+            .iter()
+            .filter_map(|ops| {
+                let (first, last) = (ops.first()?, ops.last()?);
+
+                let old = first.old_range().start..last.old_range().end;
+                let new = first.new_range().start..last.new_range().end;
+
+                let changes = ops
+                    .iter()
+                    .flat_map(|op| diff.iter_changes(op))
+                    .map(|c| Change {
+                        tag: c.tag().into(),
+                        value: c.value().to_owned(),
+                    })
+                    .collect();
+
+                Some(Hunk {
+                    old_start: old.start + 1,
+                    old_len: old.len(),
+                    new_start: new.start + 1,
+                    new_len: new.len(),
+                    changes,
+                })
+            })
+            .collect();
+
+        // adjoining words of same tag are merged, for rendering as "edited" view
+        let diff = TextDiff::from_words(old, new);
+        let words = diff
+            .iter_all_changes()
+            .fold(Vec::<Change>::new(), |mut acc, c| {
+                let tag = c.tag().into();
+
+                match acc.last_mut() {
+                    Some(last) if last.tag == tag => last.value.push_str(c.value()),
+                    _ => acc.push(Change {
+                        tag,
+                        value: c.value().to_owned(),
+                    }),
+                }
+
+                acc
+            });
+
+        Self { lines, words }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+impl Page<Post> {
+    /// `models` is expected to be fetched with `limit + 1`, to know whether next page exists
+    pub fn from_models(mut models: Vec<crate::models::Post>, limit: u32) -> anyhow::Result<Self> {
+        let next = if models.len() > limit as usize {
+            models.truncate(limit as usize);
+            models.last().map(|m| encode_cursor(&m.cursor()))
+        } else {
+            None
+        };
+
+        let items = models
+            .into_iter()
+            .map(Post::from_model)
+            .try_collect::<Vec<_>>()?;
+
+        Ok(Self { items, next })
+    }
+}
+
+pub fn encode_cursor(cursor: &crate::models::Cursor) -> String {
+    use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD as engine};
+
+    let micros = cursor.created_at.and_utc().timestamp_micros();
+
+    engine.encode(format!("{micros}.{}", cursor.id))
+}
+
+pub fn decode_cursor(s: &str) -> Option<crate::models::Cursor> {
+    use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD as engine};
+
+    let raw = String::from_utf8(engine.decode(s).ok()?).ok()?;
+    let (micros, id) = raw.split_once('.')?;
+
+    let created_at = chrono::DateTime::from_timestamp_micros(micros.parse().ok()?)?.naive_utc();
+    let id = id.parse().ok()?;
+
+    Some(crate::models::Cursor { created_at, id })
+}
+
+#[test]
+fn diff_hunks() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n";
+
+    let diff = Diff::new(old, new);
+
+    // only 3 lines around the change are included
+    let [ref hunk] = diff.lines[..] else {
+        panic!("expected a hunk");
+    };
+
+    assert_eq!(
+        (hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len),
+        (2, 7, 2, 7)
+    );
+
+    let tags = hunk.changes.iter().map(|c| c.tag).collect::<Vec<_>>();
+    let (e, i, d) = (ChangeTag::Equal, ChangeTag::Insert, ChangeTag::Delete);
+
+    assert!(tags == [e, e, e, d, i, e, e, e]);
+
+    let words = Diff::new("one two three", "one 2 three").words;
+    let words = words
+        .iter()
+        .map(|c| (c.tag, &*c.value))
+        .collect::<Vec<_>>();
+
+    assert!(words == [(e, "one "), (d, "two"), (i, "2"), (e, " three")]);
+}
+
+#[test]
+fn cursor_roundtrip() {
+    let cursor = crate::models::Cursor {
+        created_at: chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456)
+            .unwrap()
+            .naive_utc(),
+        id: 42,
+    };
+
+    assert_eq!(decode_cursor(&encode_cursor(&cursor)), Some(cursor));
+    assert_eq!(decode_cursor("not a cursor"), None);
+}
@@ -1,14 +1,41 @@
 use crate::routes::uses::*;
 
-pub async fn get<PR: PostRepository>(repo: web::Data<PR>, _: Cookies) -> impl Responder {
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 200;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Paging {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl Paging {
+    /// decoded cursor and clamped limit
+    pub fn parse(self) -> Result<(Option<models::Cursor>, u32), actix_web::Error> {
+        let Self { cursor, limit } = self;
+
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let cursor = cursor
+            .map(|c| {
+                decode_cursor(&c).ok_or_else(|| actix_web::error::ErrorBadRequest("invalid cursor"))
+            })
+            .transpose()?;
+
+        Ok((cursor, limit))
+    }
+}
+
+pub async fn get<PR: PostRepository>(
+    repo: web::Data<PR>,
+    query: web::Query<Paging>,
+) -> impl Responder {
     try_into_responder!({
-        let models = repo.all().await?;
-        let jsons = models
-            .into_iter()
-            .map(Post::from_model)
-            .try_collect::<Vec<_>>()?;
+        let (cursor, limit) = query.into_inner().parse()?;
+
+        let models = repo.page(cursor, limit + 1).await?;
 
-        HttpResponse::Ok().json(jsons)
+        HttpResponse::Ok().json(Page::from_models(models, limit)?)
     })
 }
 
@@ -18,38 +45,75 @@ pub struct Create {
     pub content: String,
 }
 
-pub async fn create<PR: PostRepository>(
+pub async fn create<PR: PostRepository + Transactional, TR: TokenRepository>(
     repo: web::Data<PR>,
     data: web::Json<Create>,
-    ck: Cookies,
+    ck: Cookies<TR>,
 ) -> impl Responder {
     try_into_responder!({
-        if ck.session.is_none() {
+        let Some(ref session) = ck.session else {
             return HttpResponse::Unauthorized().finish();
-        }
+        };
 
         let Create { content } = data.into_inner();
 
-        let id = rand::random();
+        let author_id = session.user_id()?;
         let now = chrono::Local::now().naive_utc();
 
-        let model = models::Post::new(id, content, now);
-        let id = model.id;
+        let tx = repo.begin().await?;
+
+        let id = tx.create(author_id, content, now).await?;
+        let model = tx.find_one(id).await?;
+
+        if let Some(ref model) = model {
+            tx.set_tags(id, &model.tags()).await?;
+        }
 
-        repo.create(model).await?;
-        let model = repo.find_one(id).await?;
+        tx.commit().await?;
 
         HttpResponse::Ok().json(model.map(Post::from_model).transpose()?)
     })
 }
 
+pub mod search {
+    use crate::routes::uses::*;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Search {
+        pub q: String,
+        pub limit: Option<u32>,
+    }
+
+    pub async fn get<PR: PostRepository>(
+        repo: web::Data<PR>,
+        query: web::Query<Search>,
+    ) -> impl Responder {
+        try_into_responder!({
+            let Search { q, limit } = query.into_inner();
+
+            let limit = limit.unwrap_or(super::DEFAULT_LIMIT).clamp(1, super::MAX_LIMIT);
+            let query = models::SearchQuery::parse(&q)
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("invalid query"))?;
+
+            let jsons = repo
+                .search(&query, limit)
+                .await?
+                .into_iter()
+                .map(SearchHit::from_model)
+                .try_collect::<Vec<_>>()?;
+
+            HttpResponse::Ok().json(jsons)
+        })
+    }
+}
+
 pub mod _id_ {
     use crate::routes::uses::*;
 
     pub async fn get<PR: PostRepository>(
         repo: web::Data<PR>,
-        id: web::Path<u32>,
-        _: Cookies,
+        id: web::Path<i64>,
     ) -> impl Responder {
         try_into_responder!({
             let model = repo
@@ -70,34 +134,133 @@ pub mod _id_ {
 
         #[serde(rename_all = "camelCase")]
         Deleting { is_deleted: bool },
+
+        #[serde(rename_all = "camelCase")]
+        Reverting { revert_to: models::DateTime },
     }
 
-    pub async fn update<PR: PostRepository>(
+    pub async fn update<PR: PostRepository + Transactional, TR: TokenRepository>(
         repo: web::Data<PR>,
-        id: web::Path<u32>,
+        id: web::Path<i64>,
         data: web::Json<Update>,
-        ck: Cookies,
+        ck: Cookies<TR>,
     ) -> impl Responder {
         try_into_responder!({
-            if ck.session.is_none() {
+            let Some(ref session) = ck.session else {
                 return HttpResponse::Unauthorized().finish();
+            };
+
+            let tx = repo.begin().await?;
+
+            // only the author can edit, delete or restore the post
+            match tx.find_one(*id).await? {
+                None => return HttpResponse::NotFound().finish(),
+                Some(post) if post.author_id != session.user_id()? => {
+                    return HttpResponse::Forbidden().finish();
+                },
+                Some(_) => {},
             }
 
             match data.into_inner() {
                 Update::Modify { content } => {
                     let now = chrono::Local::now().naive_utc();
-                    repo.update(*id, content, now).await?;
+                    tx.update(*id, content, now).await?;
                 },
                 Update::Deleting { is_deleted: true } => {
-                    repo.delete(*id).await?;
+                    tx.delete(*id).await?;
                 },
                 Update::Deleting { is_deleted: false } => {
-                    repo.restore(*id).await?;
+                    tx.restore(*id).await?;
+                },
+                Update::Reverting { revert_to } => {
+                    if tx.find_revision(*id, revert_to).await?.is_none() {
+                        return HttpResponse::NotFound().finish();
+                    }
+
+                    let now = chrono::Local::now().naive_utc();
+                    tx.revert(*id, revert_to, now).await?;
                 },
             }
 
-            let model = repo.find_one(*id).await?;
+            let model = tx.find_one(*id).await?;
+
+            if let Some(ref model) = model {
+                tx.set_tags(*id, &model.tags()).await?;
+            }
+
+            tx.commit().await?;
             HttpResponse::Ok().json(model.map(Post::from_model).transpose()?)
         })
     }
+
+    pub mod revisions {
+        use crate::routes::uses::*;
+
+        pub async fn get<PR: PostRepository>(
+            repo: web::Data<PR>,
+            id: web::Path<i64>,
+        ) -> impl Responder {
+            try_into_responder!({
+                let jsons = repo
+                    .find_all(*id)
+                    .await?
+                    .into_iter()
+                    .map(Post::from_model)
+                    .try_collect::<Vec<_>>()?;
+
+                HttpResponse::Ok().json(jsons)
+            })
+        }
+
+        pub mod _created_at_ {
+            use crate::routes::uses::*;
+
+            pub async fn get<PR: PostRepository>(
+                repo: web::Data<PR>,
+                path: web::Path<(i64, models::DateTime)>,
+            ) -> impl Responder {
+                try_into_responder!({
+                    let (id, created_at) = path.into_inner();
+
+                    let model = repo
+                        .find_revision(id, created_at)
+                        .await?
+                        .map(Post::from_model)
+                        .transpose()?;
+
+                    HttpResponse::Ok().json(model)
+                })
+            }
+        }
+    }
+
+    pub mod diff {
+        use crate::routes::uses::*;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub struct Between {
+            pub from: models::DateTime,
+            pub to: models::DateTime,
+        }
+
+        pub async fn get<PR: PostRepository>(
+            repo: web::Data<PR>,
+            id: web::Path<i64>,
+            query: web::Query<Between>,
+        ) -> impl Responder {
+            try_into_responder!({
+                let Between { from, to } = query.into_inner();
+
+                let (Some(old), Some(new)) = (
+                    repo.find_revision(*id, from).await?,
+                    repo.find_revision(*id, to).await?,
+                ) else {
+                    return HttpResponse::NotFound().finish();
+                };
+
+                HttpResponse::Ok().json(Diff::new(&old.content, &new.content))
+            })
+        }
+    }
 }
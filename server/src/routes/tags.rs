@@ -0,0 +1,40 @@
+use crate::routes::uses::*;
+
+pub async fn get<PR: PostRepository>(
+    repo: web::Data<PR>,
+) -> impl Responder {
+    try_into_responder!({
+        let jsons = repo
+            .tags()
+            .await?
+            .into_iter()
+            .map(Tag::from_model)
+            .try_collect::<Vec<_>>()?;
+
+        HttpResponse::Ok().json(jsons)
+    })
+}
+
+pub mod _tag_ {
+    pub mod posts {
+        use crate::routes::posts::Paging;
+        use crate::routes::uses::*;
+
+        pub async fn get<PR: PostRepository>(
+            repo: web::Data<PR>,
+            tag: web::Path<String>,
+            query: web::Query<Paging>,
+        ) -> impl Responder {
+            try_into_responder!({
+                let (cursor, limit) = query.into_inner().parse()?;
+
+                // same as normalized in `models::Post::tags`
+                let tag = tag.trim_start_matches('#').to_lowercase();
+
+                let models = repo.page_by_tag(&tag, cursor, limit + 1).await?;
+
+                HttpResponse::Ok().json(Page::from_models(models, limit)?)
+            })
+        }
+    }
+}
@@ -3,6 +3,7 @@ type DateTime = chrono::NaiveDateTime;
 #[derive(sqlx::FromRow)]
 pub struct Post {
     pub id: i64,
+    pub author_id: i64,
     pub content: String,
     pub posted_at: DateTime,
     pub created_at: DateTime,
@@ -15,16 +16,16 @@ impl crate::models::IntoModel for Post {
     fn into_model(self) -> anyhow::Result<Self::Model> {
         let Self {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
             is_deleted,
         } = self;
 
-        let id = id as u32;
-
         Ok(Self::Model {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
@@ -36,21 +37,20 @@ impl crate::models::IntoModel for Post {
 impl crate::models::FromModel for Post {
     type Model = crate::models::Post;
 
-    #[allow(clippy::identity_op)]
     fn from_model(model: Self::Model) -> anyhow::Result<Self>
     where Self: Sized {
         let Self::Model {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
             is_deleted,
         } = model;
 
-        let id = id as i64;
-
         Ok(Self {
             id,
+            author_id,
             content,
             posted_at,
             created_at,
@@ -59,28 +59,205 @@ impl crate::models::FromModel for Post {
     }
 }
 
+#[derive(sqlx::FromRow)]
+pub struct Tag {
+    pub name: String,
+    pub count: i64,
+}
+
+impl crate::models::IntoModel for Tag {
+    type Model = crate::models::Tag;
+
+    fn into_model(self) -> anyhow::Result<Self::Model> {
+        let Self { name, count } = self;
+
+        Ok(Self::Model { name, count })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    pub post: Post,
+    pub rank: f32,
+    pub headline: String,
+}
+
+impl crate::models::IntoModel for SearchHit {
+    type Model = crate::models::SearchHit;
+
+    fn into_model(self) -> anyhow::Result<Self::Model> {
+        let Self {
+            post,
+            rank,
+            headline,
+        } = self;
+
+        Ok(Self::Model {
+            post: crate::models::IntoModel::into_model(post)?,
+            rank,
+            headline,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub handle: Vec<u8>,
+    pub created_at: DateTime,
+}
+
+impl crate::models::IntoModel for User {
+    type Model = crate::models::User;
+
+    fn into_model(self) -> anyhow::Result<Self::Model> {
+        let Self {
+            id,
+            name,
+            handle,
+            created_at,
+        } = self;
+
+        let handle = webauthn_rs::prelude::Uuid::from_slice(&handle)?;
+
+        Ok(Self::Model {
+            id,
+            name,
+            handle,
+            created_at,
+        })
+    }
+}
+
 #[derive(sqlx::FromRow)]
 pub struct Key {
+    pub id: i64,
+    pub user_id: i64,
     pub content: Vec<u8>,
+    pub counter: i64,
+    pub nickname: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+    pub cred_id: Option<Vec<u8>>,
 }
 
 impl crate::models::IntoModel for Key {
-    type Model = webauthn_rs::prelude::Passkey;
+    type Model = crate::models::Key;
 
     fn into_model(self) -> anyhow::Result<Self::Model> {
-        let Self { content } = self;
+        let Self {
+            id,
+            user_id,
+            content,
+            counter,
+            nickname,
+            created_at,
+            last_used_at,
+            cred_id: _,
+        } = self;
 
-        Ok(rmp_serde::from_slice(&content)?)
+        let id = u32::try_from(id)?;
+        let passkey = rmp_serde::from_slice(&content)?;
+        let counter = u32::try_from(counter)?;
+
+        Ok(Self::Model {
+            id,
+            user_id,
+            passkey,
+            counter,
+            nickname,
+            created_at,
+            last_used_at,
+        })
     }
 }
 
 impl crate::models::FromModel for Key {
-    type Model = webauthn_rs::prelude::Passkey;
+    type Model = crate::models::Key;
 
     fn from_model(model: Self::Model) -> anyhow::Result<Self>
     where Self: Sized {
-        let content = rmp_serde::to_vec(&model)?;
+        let Self::Model {
+            id,
+            user_id,
+            passkey,
+            counter,
+            nickname,
+            created_at,
+            last_used_at,
+        } = model;
 
-        Ok(Self { content })
+        let id = i64::from(id);
+        let counter = i64::from(counter);
+        let cred_id: &[u8] = passkey.cred_id().as_ref();
+        let cred_id = Some(cred_id.to_vec());
+        let content = rmp_serde::to_vec(&passkey)?;
+
+        Ok(Self {
+            id,
+            user_id,
+            content,
+            counter,
+            nickname,
+            created_at,
+            last_used_at,
+            cred_id,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct Invitation {
+    pub code: String,
+    pub issued_by: i64,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+    pub used_at: Option<DateTime>,
+}
+
+impl crate::models::IntoModel for Invitation {
+    type Model = crate::models::Invitation;
+
+    fn into_model(self) -> anyhow::Result<Self::Model> {
+        let Self {
+            code,
+            issued_by,
+            created_at,
+            expires_at,
+            used_at,
+        } = self;
+
+        Ok(Self::Model {
+            code,
+            issued_by,
+            created_at,
+            expires_at,
+            used_at,
+        })
+    }
+}
+
+impl crate::models::FromModel for Invitation {
+    type Model = crate::models::Invitation;
+
+    fn from_model(model: Self::Model) -> anyhow::Result<Self>
+    where Self: Sized {
+        let Self::Model {
+            code,
+            issued_by,
+            created_at,
+            expires_at,
+            used_at,
+        } = model;
+
+        Ok(Self {
+            code,
+            issued_by,
+            created_at,
+            expires_at,
+            used_at,
+        })
     }
 }
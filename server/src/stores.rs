@@ -1,76 +1,590 @@
-use core::any::Any;
 use core::hash::Hash;
+use core::marker::PhantomData;
+use core::time::Duration;
+use std::time::Instant;
 
 use anyhow::Result;
 
+/// store of values of `T`, each store has its own namespace
 pub trait Store<T> {
     type Key;
 
     async fn entry(&self, key: Self::Key) -> Result<impl Entry<T>>;
 }
 
+/// value of a key in the store, expired values are treated as absent
 pub trait Entry<T> {
     async fn is_empty(&self) -> Result<bool>;
 
+    /// sets only if absent, `false` if present or the store is full
     async fn set(self, val: T) -> Result<bool>;
-    async fn get(self) -> Result<Option<T>>;
+    /// same as `set`, but the value is treated as absent after `ttl`
+    async fn set_with_ttl(self, val: T, ttl: Duration) -> Result<bool>;
+    /// gets without removing
+    async fn get(&self) -> Result<Option<T>>
+    where T: Clone;
+    /// gets and removes at once
+    async fn take(self) -> Result<Option<T>>;
+    /// sets and returns the previous value at once, expiry of the previous one is kept
+    async fn replace(self, val: T) -> Result<Option<T>>;
+    /// sets only if the current value equals `current`, expiry of the current one is kept
+    async fn compare_and_set(self, current: &T, val: T) -> Result<bool>
+    where T: PartialEq;
 }
 
-pub struct InMemoryStore<K>(dashmap::DashMap<K, Box<dyn Any + Send + Sync>>);
+pub struct InMemoryStore<K, T> {
+    map: dashmap::DashMap<K, Slot<T>>,
+    capacity: usize,
+}
+
+struct Slot<T> {
+    val: T,
+    expires_at: Option<Instant>,
+}
+
+impl<T> Slot<T> {
+    fn is_expired(&self, now: Instant) -> bool { self.expires_at.is_some_and(|t| t <= now) }
+}
+
+impl<K: Eq + Hash, T> InMemoryStore<K, T> {
+    /// default of the number of entries
+    pub const DEFAULT_CAPACITY: usize = 10_000;
 
-impl<K: Eq + Hash> InMemoryStore<K> {
-    pub fn new() -> Self { Self(dashmap::DashMap::new()) }
+    pub fn new() -> Self { Self::with_capacity(Self::DEFAULT_CAPACITY) }
+
+    /// store rejects new entries if it has `capacity` entries, even after sweeping
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: dashmap::DashMap::new(),
+            capacity,
+        }
+    }
+
+    /// removes expired entries
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.map.retain(|_, slot| !slot.is_expired(now));
+    }
 }
 
-impl<K: Eq + Hash> Default for InMemoryStore<K> {
+impl<K: Eq + Hash, T> Default for InMemoryStore<K, T> {
     fn default() -> Self { Self::new() }
 }
 
-impl<K: Eq + Hash, T: Any + Send + Sync> Store<T> for InMemoryStore<K> {
+impl<K: Eq + Hash, T> Store<T> for InMemoryStore<K, T> {
     type Key = K;
 
     async fn entry(&self, key: Self::Key) -> Result<impl Entry<T>> {
-        Ok(InMemoryEntry(self.0.entry(key)))
+        // must be checked before locking by the entry
+        if self.map.len() >= self.capacity {
+            self.sweep();
+        }
+
+        let is_full = self.map.len() >= self.capacity;
+
+        Ok(InMemoryEntry {
+            entry: self.map.entry(key),
+            is_full,
+        })
     }
 }
 
-pub struct InMemoryEntry<'a, K, V>(dashmap::mapref::entry::Entry<'a, K, V>);
+pub struct InMemoryEntry<'a, K, T> {
+    entry: dashmap::mapref::entry::Entry<'a, K, Slot<T>>,
+    is_full: bool,
+}
 
-impl<K: Eq + Hash, T: Any + Send + Sync> Entry<T>
-    for InMemoryEntry<'_, K, Box<dyn Any + Send + Sync>>
-{
-    async fn is_empty(&self) -> Result<bool> {
+impl<K: Eq + Hash, T> InMemoryEntry<'_, K, T> {
+    /// current slot, `None` if it's absent or expired
+    fn current(&self) -> Option<&Slot<T>> {
         use dashmap::mapref::entry::Entry;
 
-        match self.0 {
-            Entry::Occupied(_) => Ok(false),
-            Entry::Vacant(_) => Ok(true),
+        match self.entry {
+            Entry::Occupied(ref e) if !e.get().is_expired(Instant::now()) => Some(e.get()),
+            _ => None,
         }
     }
 
-    async fn set(self, val: T) -> Result<bool> {
+    fn insert(self, val: T, ttl: Option<Duration>) -> bool {
         use dashmap::mapref::entry::Entry;
 
-        let is_inserted = match self.0 {
+        let now = Instant::now();
+        let slot = Slot {
+            val,
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+
+        match self.entry {
+            Entry::Occupied(mut e) if e.get().is_expired(now) => {
+                e.insert(slot);
+                true
+            },
             Entry::Occupied(_) => false,
+            // rejected as same as present ones, callers can back off
+            Entry::Vacant(_) if self.is_full => false,
             Entry::Vacant(e) => {
-                e.insert(Box::new(val));
+                e.insert(slot);
                 true
             },
-        };
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> Entry<T> for InMemoryEntry<'_, K, T> {
+    async fn is_empty(&self) -> Result<bool> { Ok(self.current().is_none()) }
 
-        Ok(is_inserted)
+    async fn set(self, val: T) -> Result<bool> { Ok(self.insert(val, None)) }
+
+    async fn set_with_ttl(self, val: T, ttl: Duration) -> Result<bool> {
+        Ok(self.insert(val, Some(ttl)))
+    }
+
+    async fn get(&self) -> Result<Option<T>>
+    where T: Clone {
+        Ok(self.current().map(|slot| slot.val.clone()))
     }
 
-    async fn get(self) -> Result<Option<T>> {
+    async fn take(self) -> Result<Option<T>> {
         use dashmap::mapref::entry::Entry;
 
-        match self.0 {
+        match self.entry {
             Entry::Vacant(_) => Ok(None),
-            Entry::Occupied(e) => match e.get().downcast_ref::<T>() {
-                Some(_) => Ok(Some(*e.remove().downcast::<T>().unwrap())),
-                None => Err(anyhow::anyhow!("unmatched type")),
+            Entry::Occupied(e) => {
+                let slot = e.remove();
+                Ok((!slot.is_expired(Instant::now())).then_some(slot.val))
+            },
+        }
+    }
+
+    async fn replace(self, val: T) -> Result<Option<T>> {
+        use dashmap::mapref::entry::Entry;
+
+        match self.entry {
+            Entry::Occupied(mut e) if !e.get().is_expired(Instant::now()) => {
+                let expires_at = e.get().expires_at;
+                Ok(Some(e.insert(Slot { val, expires_at }).val))
+            },
+            Entry::Occupied(mut e) => {
+                e.insert(Slot {
+                    val,
+                    expires_at: None,
+                });
+                Ok(None)
+            },
+            Entry::Vacant(_) if self.is_full => Err(anyhow::anyhow!("store is full")),
+            Entry::Vacant(e) => {
+                e.insert(Slot {
+                    val,
+                    expires_at: None,
+                });
+                Ok(None)
+            },
+        }
+    }
+
+    async fn compare_and_set(self, current: &T, val: T) -> Result<bool>
+    where T: PartialEq {
+        use dashmap::mapref::entry::Entry;
+
+        match self.entry {
+            Entry::Occupied(mut e)
+                if !e.get().is_expired(Instant::now()) && e.get().val == *current =>
+            {
+                e.get_mut().val = val;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+}
+
+/// store in postgres, shared by all instances of server
+///
+/// values are serialized as `MessagePack`, and namespaced by `kind`
+pub struct PgStore<K, T> {
+    pool: sqlx::PgPool,
+    kind: &'static str,
+    _marker: PhantomData<fn(K) -> T>,
+}
+
+impl<K, T> PgStore<K, T> {
+    /// `kind` is persisted, so it must be stable across builds and unique among stores
+    pub const fn new(pool: sqlx::PgPool, kind: &'static str) -> Self {
+        Self {
+            pool,
+            kind,
+            _marker: PhantomData,
+        }
+    }
+
+    /// removes expired entries
+    pub async fn sweep(&self) -> Result<()> {
+        #[rustfmt::skip]
+        const QUERY: &str = "DELETE FROM store_entries \
+                             WHERE kind = $1 AND expires_at <= NOW() AT TIME ZONE 'UTC'";
+
+        sqlx::query(QUERY)
+            .bind(self.kind)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl<K, T> Store<T> for PgStore<K, T>
+where
+    K: ToString,
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    type Key = K;
+
+    async fn entry(&self, key: Self::Key) -> Result<impl Entry<T>> {
+        Ok(PgEntry {
+            pool: &self.pool,
+            kind: self.kind,
+            key: key.to_string(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+pub struct PgEntry<'a, T> {
+    pool: &'a sqlx::PgPool,
+    kind: &'static str,
+    key: String,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> PgEntry<'_, T>
+where T: serde::Serialize + serde::de::DeserializeOwned
+{
+    async fn insert(self, val: T, ttl: Option<Duration>) -> Result<bool> {
+        // replaces only expired one
+        #[rustfmt::skip]
+        const QUERY: &str = "INSERT INTO store_entries (kind, key, value, expires_at) \
+                             VALUES ($1, $2, $3, $4) \
+                             ON CONFLICT (kind, key) DO UPDATE \
+                             SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at \
+                             WHERE store_entries.expires_at <= NOW() AT TIME ZONE 'UTC'";
+
+        let expires_at = ttl
+            .map(chrono::Duration::from_std)
+            .transpose()?
+            .map(|ttl| chrono::Local::now().naive_utc() + ttl);
+
+        let result = sqlx::query(QUERY)
+            .bind(self.kind)
+            .bind(&self.key)
+            .bind(rmp_serde::to_vec_named(&val)?)
+            .bind(expires_at)
+            .execute(self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+impl<T> Entry<T> for PgEntry<'_, T>
+where T: serde::Serialize + serde::de::DeserializeOwned
+{
+    async fn is_empty(&self) -> Result<bool> {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT NOT EXISTS ( \
+                                 SELECT 1 FROM store_entries \
+                                 WHERE kind = $1 AND key = $2 \
+                                 AND (expires_at IS NULL OR expires_at > NOW() AT TIME ZONE 'UTC') \
+                             )";
+
+        let is_empty = sqlx::query_scalar::<_, bool>(QUERY)
+            .bind(self.kind)
+            .bind(&self.key)
+            .fetch_one(self.pool)
+            .await?;
+
+        Ok(is_empty)
+    }
+
+    async fn set(self, val: T) -> Result<bool> { self.insert(val, None).await }
+
+    async fn set_with_ttl(self, val: T, ttl: Duration) -> Result<bool> {
+        self.insert(val, Some(ttl)).await
+    }
+
+    async fn get(&self) -> Result<Option<T>>
+    where T: Clone {
+        #[rustfmt::skip]
+        const QUERY: &str = "SELECT value FROM store_entries \
+                             WHERE kind = $1 AND key = $2 \
+                             AND (expires_at IS NULL OR expires_at > NOW() AT TIME ZONE 'UTC')";
+
+        let value = sqlx::query_scalar::<_, Vec<u8>>(QUERY)
+            .bind(self.kind)
+            .bind(&self.key)
+            .fetch_optional(self.pool)
+            .await?;
+
+        Ok(value.map(|v| rmp_serde::from_slice(&v)).transpose()?)
+    }
+
+    async fn take(self) -> Result<Option<T>> {
+        // taken atomically, only one of instances can get it
+        #[rustfmt::skip]
+        const QUERY: &str = "DELETE FROM store_entries \
+                             WHERE kind = $1 AND key = $2 \
+                             RETURNING value, (expires_at <= NOW() AT TIME ZONE 'UTC') AS is_expired";
+
+        let row = sqlx::query_as::<_, (Vec<u8>, Option<bool>)>(QUERY)
+            .bind(self.kind)
+            .bind(&self.key)
+            .fetch_optional(self.pool)
+            .await?;
+
+        match row {
+            None | Some((_, Some(true))) => Ok(None),
+            Some((value, _)) => Ok(Some(rmp_serde::from_slice(&value)?)),
+        }
+    }
+
+    async fn replace(self, val: T) -> Result<Option<T>> {
+        #[rustfmt::skip]
+        const QUERY_0: &str = "SELECT value, (expires_at <= NOW() AT TIME ZONE 'UTC') AS is_expired \
+                               FROM store_entries \
+                               WHERE kind = $1 AND key = $2 \
+                               FOR UPDATE";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "INSERT INTO store_entries (kind, key, value, expires_at) \
+                               VALUES ($1, $2, $3, NULL) \
+                               ON CONFLICT (kind, key) DO UPDATE \
+                               SET value = EXCLUDED.value, \
+                                   expires_at = CASE \
+                                       WHEN store_entries.expires_at <= NOW() AT TIME ZONE 'UTC' THEN NULL \
+                                       ELSE store_entries.expires_at \
+                                   END";
+
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query_as::<_, (Vec<u8>, Option<bool>)>(QUERY_0)
+            .bind(self.kind)
+            .bind(&self.key)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        sqlx::query(QUERY_1)
+            .bind(self.kind)
+            .bind(&self.key)
+            .bind(rmp_serde::to_vec_named(&val)?)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        match row {
+            None | Some((_, Some(true))) => Ok(None),
+            Some((value, _)) => Ok(Some(rmp_serde::from_slice(&value)?)),
+        }
+    }
+
+    async fn compare_and_set(self, current: &T, val: T) -> Result<bool>
+    where T: PartialEq {
+        #[rustfmt::skip]
+        const QUERY_0: &str = "SELECT value FROM store_entries \
+                               WHERE kind = $1 AND key = $2 \
+                               AND (expires_at IS NULL OR expires_at > NOW() AT TIME ZONE 'UTC') \
+                               FOR UPDATE";
+
+        #[rustfmt::skip]
+        const QUERY_1: &str = "UPDATE store_entries \
+                               SET value = $3 \
+                               WHERE kind = $1 AND key = $2";
+
+        let mut tx = self.pool.begin().await?;
+
+        let value = sqlx::query_scalar::<_, Vec<u8>>(QUERY_0)
+            .bind(self.kind)
+            .bind(&self.key)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(value) = value else {
+            return Ok(false);
+        };
+
+        if rmp_serde::from_slice::<T>(&value)? != *current {
+            return Ok(false);
+        }
+
+        sqlx::query(QUERY_1)
+            .bind(self.kind)
+            .bind(&self.key)
+            .bind(rmp_serde::to_vec_named(&val)?)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+/// one of stores, selected by configuration
+pub enum EitherStore<K, T> {
+    InMemory(InMemoryStore<K, T>),
+    Pg(PgStore<K, T>),
+}
+
+impl<K: Eq + Hash, T> EitherStore<K, T> {
+    /// removes expired entries
+    pub async fn sweep(&self) -> Result<()> {
+        match self {
+            Self::InMemory(store) => {
+                store.sweep();
+                Ok(())
             },
+            Self::Pg(store) => store.sweep().await,
+        }
+    }
+}
+
+impl<K, T> Store<T> for EitherStore<K, T>
+where
+    InMemoryStore<K, T>: Store<T, Key = K>,
+    PgStore<K, T>: Store<T, Key = K>,
+{
+    type Key = K;
+
+    async fn entry(&self, key: Self::Key) -> Result<impl Entry<T>> {
+        Ok(match self {
+            Self::InMemory(store) => EitherEntry::InMemory(store.entry(key).await?),
+            Self::Pg(store) => EitherEntry::Pg(store.entry(key).await?),
+        })
+    }
+}
+
+pub enum EitherEntry<A, B> {
+    InMemory(A),
+    Pg(B),
+}
+
+impl<T, A: Entry<T>, B: Entry<T>> Entry<T> for EitherEntry<A, B> {
+    async fn is_empty(&self) -> Result<bool> {
+        match self {
+            Self::InMemory(entry) => entry.is_empty().await,
+            Self::Pg(entry) => entry.is_empty().await,
+        }
+    }
+
+    async fn set(self, val: T) -> Result<bool> {
+        match self {
+            Self::InMemory(entry) => entry.set(val).await,
+            Self::Pg(entry) => entry.set(val).await,
+        }
+    }
+
+    async fn set_with_ttl(self, val: T, ttl: Duration) -> Result<bool> {
+        match self {
+            Self::InMemory(entry) => entry.set_with_ttl(val, ttl).await,
+            Self::Pg(entry) => entry.set_with_ttl(val, ttl).await,
+        }
+    }
+
+    async fn get(&self) -> Result<Option<T>>
+    where T: Clone {
+        match self {
+            Self::InMemory(entry) => entry.get().await,
+            Self::Pg(entry) => entry.get().await,
+        }
+    }
+
+    async fn take(self) -> Result<Option<T>> {
+        match self {
+            Self::InMemory(entry) => entry.take().await,
+            Self::Pg(entry) => entry.take().await,
+        }
+    }
+
+    async fn replace(self, val: T) -> Result<Option<T>> {
+        match self {
+            Self::InMemory(entry) => entry.replace(val).await,
+            Self::Pg(entry) => entry.replace(val).await,
+        }
+    }
+
+    async fn compare_and_set(self, current: &T, val: T) -> Result<bool>
+    where T: PartialEq {
+        match self {
+            Self::InMemory(entry) => entry.compare_and_set(current, val).await,
+            Self::Pg(entry) => entry.compare_and_set(current, val).await,
         }
     }
 }
+
+#[tokio::test]
+async fn in_memory_expiry_and_capacity() {
+    let store = InMemoryStore::<u32, String>::with_capacity(2);
+
+    assert!(store.entry(0).await.unwrap().set("a".into()).await.unwrap());
+    assert!(!store.entry(0).await.unwrap().set("b".into()).await.unwrap());
+
+    // expired ones are absent, and can be overwritten
+    let entry = store.entry(1).await.unwrap();
+    assert!(entry.set_with_ttl("c".into(), Duration::ZERO).await.unwrap());
+
+    let entry = store.entry(1).await.unwrap();
+    assert!(entry.is_empty().await.unwrap());
+    assert_eq!(entry.get().await.unwrap(), None);
+    assert!(entry.set("d".into()).await.unwrap());
+
+    // full, even after sweeping
+    assert!(!store.entry(2).await.unwrap().set("e".into()).await.unwrap());
+
+    let entry = store.entry(1).await.unwrap();
+    assert!(entry.set_with_ttl("f".into(), Duration::ZERO).await.is_ok_and(|set| !set));
+    assert_eq!(store.entry(1).await.unwrap().take().await.unwrap().as_deref(), Some("d"));
+
+    // has a room again
+    assert!(store.entry(2).await.unwrap().set_with_ttl("g".into(), Duration::ZERO).await.unwrap());
+    store.sweep();
+    assert_eq!(store.map.len(), 1);
+}
+
+#[tokio::test]
+async fn in_memory_take_replace_compare_and_set() {
+    let store = InMemoryStore::<u32, String>::with_capacity(1);
+
+    assert_eq!(store.entry(0).await.unwrap().take().await.unwrap(), None);
+    assert_eq!(store.entry(0).await.unwrap().replace("a".into()).await.unwrap(), None);
+
+    let old = store.entry(0).await.unwrap().replace("b".into()).await.unwrap();
+    assert_eq!(old.as_deref(), Some("a"));
+
+    // full, so nothing can be added
+    assert!(store.entry(1).await.unwrap().replace("c".into()).await.is_err());
+
+    // swapped only when the current one matches
+    let entry = store.entry(0).await.unwrap();
+    assert!(!entry.compare_and_set(&"a".into(), "c".into()).await.unwrap());
+    let entry = store.entry(0).await.unwrap();
+    assert!(entry.compare_and_set(&"b".into(), "c".into()).await.unwrap());
+    let entry = store.entry(1).await.unwrap();
+    assert!(!entry.compare_and_set(&"c".into(), "d".into()).await.unwrap());
+
+    // taken only once
+    assert_eq!(store.entry(0).await.unwrap().take().await.unwrap().as_deref(), Some("c"));
+    assert_eq!(store.entry(0).await.unwrap().take().await.unwrap(), None);
+
+    // expired ones are neither compared nor taken
+    let entry = store.entry(0).await.unwrap();
+    assert!(entry.set_with_ttl("d".into(), Duration::ZERO).await.unwrap());
+    let entry = store.entry(0).await.unwrap();
+    assert!(!entry.compare_and_set(&"d".into(), "e".into()).await.unwrap());
+    assert_eq!(store.entry(0).await.unwrap().take().await.unwrap(), None);
+
+    // replacing keeps expiry
+    let entry = store.entry(0).await.unwrap();
+    assert!(entry.set_with_ttl("f".into(), Duration::from_mins(1)).await.unwrap());
+    let old = store.entry(0).await.unwrap().replace("g".into()).await.unwrap();
+    assert_eq!(old.as_deref(), Some("f"));
+    assert!(store.map.get(&0).is_some_and(|slot| slot.expires_at.is_some()));
+}